@@ -0,0 +1,464 @@
+use std::str::FromStr;
+
+use bevy::{app::AppExit, prelude::*, utils::HashSet};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{
+    boat::{Boat, BoatState, Dock},
+    building::{can_afford, Building, BuildingType},
+    command::GameCommand,
+    config::GameConfig,
+    faction::{Faction, FactionId, Factions, Owner},
+    fog::{FogOfWar, Sight},
+    house::House,
+    island::{Island, IslandNR, Size as IslandSize},
+    layout::find_free_spot,
+    market::{Market, CURRENCY},
+    orders::{Order, OrderQueue},
+    person::{Person, PersonTask},
+    resource::{NaturalResource, NaturalResourceType},
+    GameStates, GameTime, Headless, MyStages, WorldRng,
+};
+
+/// People the AI sends on each boat to settle a new island.
+const SETTLERS: usize = 2;
+/// Ore the AI keeps before selling the rest.
+const MARKET_SURPLUS: u32 = 20;
+/// Most the AI buys in one turn.
+const MARKET_LOT: u32 = 5;
+const HEADLESS_REPORT_INTERVAL: f32 = 30.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum AiDifficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for AiDifficulty {
+    fn default() -> Self {
+        AiDifficulty::Normal
+    }
+}
+
+impl FromStr for AiDifficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(AiDifficulty::Easy),
+            "normal" => Ok(AiDifficulty::Normal),
+            "hard" => Ok(AiDifficulty::Hard),
+            _ => Err(format!("unknown AI difficulty {:?}", s)),
+        }
+    }
+}
+
+impl AiDifficulty {
+    /// Seconds between the AI's turns.
+    fn think_interval(self) -> f32 {
+        match self {
+            AiDifficulty::Easy => 6.,
+            AiDifficulty::Normal => 3.,
+            AiDifficulty::Hard => 1.5,
+        }
+    }
+
+    /// How many people get new orders each turn.
+    fn orders_per_turn(self) -> usize {
+        match self {
+            AiDifficulty::Easy => 1,
+            AiDifficulty::Normal => 3,
+            AiDifficulty::Hard => 6,
+        }
+    }
+
+    fn builds_per_turn(self) -> usize {
+        match self {
+            AiDifficulty::Easy => 1,
+            AiDifficulty::Normal => 2,
+            AiDifficulty::Hard => 3,
+        }
+    }
+
+    /// Multiple of a building's cost the island must hold before the AI builds there.
+    fn reserve_factor(self) -> u32 {
+        match self {
+            AiDifficulty::Easy => 2,
+            AiDifficulty::Normal | AiDifficulty::Hard => 1,
+        }
+    }
+
+    /// Population the faction waits for before it settles unclaimed islands.
+    fn expansion_population(self) -> usize {
+        match self {
+            AiDifficulty::Easy => 30,
+            AiDifficulty::Normal => 20,
+            AiDifficulty::Hard => 12,
+        }
+    }
+}
+
+pub struct AiPlayer {
+    pub faction: FactionId,
+    pub difficulty: AiDifficulty,
+    timer: Timer,
+}
+
+/// Computer-controlled factions. In a headless run every faction is one.
+#[derive(Default)]
+pub struct AiPlayers(pub Vec<AiPlayer>);
+
+fn setup_ai_players(
+    config: Res<GameConfig>,
+    headless: Res<Headless>,
+    factions: Res<Factions>,
+    mut ai_players: ResMut<AiPlayers>,
+) {
+    for faction in factions.list.iter() {
+        let difficulty = if headless.enabled {
+            headless
+                .difficulties
+                .get(faction.id.0)
+                .copied()
+                .unwrap_or(config.ai_difficulty)
+        } else if faction.id != factions.player {
+            config.ai_difficulty
+        } else {
+            continue;
+        };
+        println!("{} is played by the {:?} AI", faction.name, difficulty);
+        ai_players.0.push(AiPlayer {
+            faction: faction.id,
+            difficulty,
+            timer: Timer::from_seconds(difficulty.think_interval(), true),
+        });
+    }
+}
+
+/// The resource the faction should gather next: whatever it lacks to keep building, then its
+/// scarcest stock. Easy AIs don't plan and take whatever is closest.
+fn wanted_resource(
+    faction: &Faction,
+    difficulty: AiDifficulty,
+    config: &GameConfig,
+) -> Option<NaturalResourceType> {
+    if difficulty == AiDifficulty::Easy {
+        return None;
+    }
+    let stock = |nr_type: &NaturalResourceType| faction.stock.get(nr_type).copied().unwrap_or(0);
+    // the AI only builds houses and docks
+    let short = [BuildingType::House, BuildingType::Dock]
+        .iter()
+        .flat_map(|building| {
+            building
+                .cost(config)
+                .into_iter()
+                .filter(|(nr_type, amount)| stock(nr_type) < *amount * difficulty.reserve_factor())
+                .map(|(nr_type, _)| nr_type)
+        });
+    short
+        .into_iter()
+        .next()
+        .or_else(|| NaturalResourceType::ALL.iter().copied().min_by_key(stock))
+}
+
+/// Every AI whose turn came up looks at the map and sends the same commands a player could.
+fn run_ai_players(
+    time: Res<GameTime>,
+    config: Res<GameConfig>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    mut world_rng: ResMut<WorldRng>,
+    mut ai_players: ResMut<AiPlayers>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+    islands_query: Query<(Entity, &GlobalTransform, &IslandSize, &Owner, &IslandNR), With<Island>>,
+    buildings_query: Query<(&GlobalTransform, &Sprite), With<Building>>,
+    people_query: Query<(Entity, &GlobalTransform, &Person, &Owner, &OrderQueue)>,
+    houses_query: Query<&House>,
+    docks_query: Query<(Entity, &Dock, &Owner)>,
+    boats_query: Query<(Entity, &Boat)>,
+    markets_query: Query<&Market>,
+    resources_query: Query<(Entity, &GlobalTransform, &NaturalResource)>,
+) {
+    let rng = &mut world_rng.0;
+    for ai in ai_players.0.iter_mut() {
+        if !ai.timer.tick(time.delta_seconds()).just_finished() {
+            continue;
+        }
+        let faction = match factions.get(ai.faction) {
+            Some(faction) => faction,
+            None => continue,
+        };
+        let difficulty = ai.difficulty;
+        let mine = Owner(Some(faction.id));
+
+        // develop the richest islands first
+        let mut own_islands: Vec<(Entity, Vec2, f32, u32)> = islands_query
+            .iter()
+            .filter(|(_, _, _, owner, _)| **owner == mine)
+            .map(|(island, transform, size, _, stock)| {
+                let total: u32 = stock.0.values().sum();
+                (
+                    island,
+                    transform.translation.truncate(),
+                    size.width / 2.,
+                    total,
+                )
+            })
+            .collect();
+        own_islands.sort_by_key(|(_, _, _, total)| std::cmp::Reverse(*total));
+
+        let building = BuildingType::House;
+        let reserve: Vec<(NaturalResourceType, u32)> = building
+            .cost(&config)
+            .into_iter()
+            .map(|(nr_type, amount)| (nr_type, amount * difficulty.reserve_factor()))
+            .collect();
+        let mut buildings: Vec<(Vec2, Vec2)> = buildings_query
+            .iter()
+            .map(|(transform, sprite)| (transform.translation.truncate(), sprite.size))
+            .collect();
+        let mut built = 0;
+        for (island, center, radius, _) in own_islands.iter() {
+            if built >= difficulty.builds_per_turn() {
+                break;
+            }
+            let rich_enough = islands_query
+                .get(*island)
+                .map_or(false, |(_, _, _, _, stock)| can_afford(stock, &reserve));
+            if !rich_enough {
+                continue;
+            }
+            let spot = find_free_spot(
+                rng, building, &config, *island, *center, *radius, &buildings,
+            );
+            if let Some(position) = spot {
+                game_commands.send(GameCommand::Build {
+                    faction: faction.id,
+                    building,
+                    position,
+                });
+                buildings.push((position, building.size(&config)));
+                built += 1;
+            }
+        }
+
+        // sell surplus ore for Gold, and spend Gold on whatever is short
+        let wanted = wanted_resource(faction, difficulty, &config);
+        if let Some((island, _, _, _)) = own_islands.first() {
+            if let (Ok((_, _, _, _, stock)), Ok(market)) =
+                (islands_query.get(*island), markets_query.get(*island))
+            {
+                let held =
+                    |nr_type: &NaturalResourceType| stock.0.get(nr_type).copied().unwrap_or(0);
+                for ore in [NaturalResourceType::Coal, NaturalResourceType::Iron].iter() {
+                    if held(ore) > MARKET_SURPLUS {
+                        game_commands.send(GameCommand::MarketTrade {
+                            faction: faction.id,
+                            island: *island,
+                            resource: *ore,
+                            amount: -((held(ore) - MARKET_SURPLUS) as i32),
+                        });
+                    }
+                }
+                if let Some(wanted) = wanted.filter(|wanted| *wanted != CURRENCY) {
+                    let affordable = (held(&CURRENCY) as f32 / market.price(wanted)).floor() as u32;
+                    let lot = affordable.min(MARKET_LOT);
+                    if lot > 0 {
+                        game_commands.send(GameCommand::MarketTrade {
+                            faction: faction.id,
+                            island: *island,
+                            resource: wanted,
+                            amount: lot as i32,
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut idle: Vec<(Entity, Vec2, Option<Entity>)> = people_query
+            .iter()
+            .filter(|(_, _, person, owner, orders)| {
+                **owner == mine
+                    && orders.0.is_empty()
+                    && matches!(person.task, PersonTask::Idle | PersonTask::Wandering)
+            })
+            .map(|(worker, transform, person, _, _)| {
+                let island = person
+                    .house
+                    .and_then(|house| houses_query.get(house).ok())
+                    .map(|house| house.island);
+                (worker, transform.translation.truncate(), island)
+            })
+            .collect();
+
+        // once big enough, settle the nearest unclaimed island it has discovered: dock, then
+        // boat, then sail
+        let home = faction.home_island.and_then(|island| {
+            islands_query
+                .get(island)
+                .ok()
+                .map(|(_, transform, size, _, _)| {
+                    (island, transform.translation.truncate(), size.width / 2.)
+                })
+        });
+        let target = home.and_then(|(_, home_center, _)| {
+            islands_query
+                .iter()
+                .filter(|(island, _, _, owner, _)| {
+                    owner.0.is_none() && fog.knows_island(faction.id, *island)
+                })
+                .map(|(island, transform, _, _, _)| (island, transform.translation.truncate()))
+                .min_by(|a, b| {
+                    (a.1 - home_center)
+                        .length()
+                        .partial_cmp(&(b.1 - home_center).length())
+                        .unwrap()
+                })
+        });
+        if let Some((home, home_center, home_radius)) = home {
+            if faction.population >= difficulty.expansion_population() {
+                let dock = docks_query
+                    .iter()
+                    .find(|(_, dock, owner)| dock.island == home && **owner == mine)
+                    .map(|(dock, _, _)| dock);
+                let boat = dock.and_then(|dock| {
+                    boats_query
+                        .iter()
+                        .find(|(_, boat)| boat.dock == dock)
+                        .map(|(boat, info)| (boat, info.state))
+                });
+                match (dock, boat) {
+                    (None, _) => {
+                        // on the shore facing the target, a little to either side; with nothing
+                        // discovered yet the dock looks out toward the middle of the world
+                        let toward = match target {
+                            Some((_, target_center)) => target_center - home_center,
+                            None => -home_center,
+                        };
+                        let angle = if toward.length() > 0. {
+                            toward.y.atan2(toward.x) + rng.gen_range(-0.6..0.6)
+                        } else {
+                            rng.gen_range(0.0..std::f32::consts::TAU)
+                        };
+                        let inset = BuildingType::Dock.size(&config).length() / 2. + 1.;
+                        let position = home_center
+                            + Vec2::new(angle.cos(), angle.sin()) * (home_radius - inset);
+                        game_commands.send(GameCommand::Build {
+                            faction: faction.id,
+                            building: BuildingType::Dock,
+                            position,
+                        });
+                    }
+                    (Some(dock), None) => game_commands.send(GameCommand::BuildBoat {
+                        faction: faction.id,
+                        dock,
+                    }),
+                    (Some(_), Some((boat, BoatState::Docked))) => {
+                        if let Some((target, _)) = target {
+                            let mut passengers = Vec::new();
+                            idle.retain(|(worker, _, island)| {
+                                let boarding = *island == Some(home) && passengers.len() < SETTLERS;
+                                if boarding {
+                                    passengers.push(*worker);
+                                }
+                                !boarding
+                            });
+                            if !passengers.is_empty() {
+                                game_commands.send(GameCommand::Sail {
+                                    faction: faction.id,
+                                    boat,
+                                    island: target,
+                                    passengers,
+                                    cargo: BuildingType::House.cost(&config),
+                                });
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut claimed = HashSet::default();
+        for (worker, position, _) in idle.into_iter().take(difficulty.orders_per_turn()) {
+            let nearest = resources_query
+                .iter()
+                .filter(|(resource, transform, nr)| {
+                    !claimed.contains(resource)
+                        && wanted.map_or(true, |wanted| nr.0 == wanted)
+                        && fog.sight(faction.id, transform.translation.truncate())
+                            != Sight::Unexplored
+                })
+                .map(|(resource, transform, _)| {
+                    (
+                        resource,
+                        (transform.translation.truncate() - position).length(),
+                    )
+                })
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            if let Some((resource, _)) = nearest {
+                claimed.insert(resource);
+                game_commands.send(GameCommand::Order {
+                    faction: faction.id,
+                    people: vec![worker],
+                    order: Order::Gather(resource),
+                    queue: false,
+                });
+            }
+        }
+    }
+}
+
+pub struct HeadlessReportTimer(Timer);
+
+/// Prints the standings now and then, and ends a headless run once its time is up.
+fn report_headless_run(
+    time: Res<GameTime>,
+    headless: Res<Headless>,
+    factions: Res<Factions>,
+    mut timer: ResMut<HeadlessReportTimer>,
+    mut app_exit: ResMut<Events<AppExit>>,
+) {
+    let finished = time.seconds_since_startup() >= headless.duration;
+    if !timer.0.tick(time.delta_seconds()).just_finished() && !finished {
+        return;
+    }
+    println!("== standings at {:.0}s ==", time.seconds_since_startup());
+    for faction in factions.list.iter() {
+        println!(
+            "{}: score {}, {} islands, {} houses, {} people, stock {:?}",
+            faction.name,
+            faction.score,
+            faction.islands,
+            faction.houses,
+            faction.population,
+            faction.stock
+        );
+    }
+    if finished {
+        app_exit.send(AppExit);
+    }
+}
+
+pub struct AiPlugin;
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let headless = app
+            .resources()
+            .get::<Headless>()
+            .map_or(false, |headless| headless.enabled);
+        app.init_resource::<AiPlayers>()
+            .add_world_gen_system(MyStages::People, setup_ai_players.system())
+            .add_game_system(run_ai_players.system());
+        if headless {
+            app.add_resource(HeadlessReportTimer(Timer::from_seconds(
+                HEADLESS_REPORT_INTERVAL,
+                true,
+            )))
+            .add_game_system(report_headless_run.system());
+        }
+    }
+}
@@ -0,0 +1,659 @@
+use std::collections::VecDeque;
+
+use bevy::{math::vec3, prelude::*, utils::HashMap};
+use rand::prelude::SliceRandom;
+
+use crate::{
+    building::{can_afford, format_cost, pay, Building, BuildingType},
+    command::GameCommand,
+    config::GameConfig,
+    faction::{Factions, Owner},
+    feed::GameEvent,
+    fog::FogOfWar,
+    house::{spawn_house, House, HouseTier},
+    island::{Island, IslandNR, Size as IslandSize},
+    layout::find_free_spot,
+    names::NameGenerator,
+    orders::OrderQueue,
+    panel::SelectedIsland,
+    person::{Person, PersonTask, TargetPosition},
+    resource::NaturalResourceType,
+    CursorPosition, GameStates, GameTime, Materials, Selected, WorldRng,
+};
+
+pub const BOAT_CAPACITY: usize = 4;
+/// How far inside an island's edge a dock may stand.
+pub const COAST_BAND: f32 = 12.;
+/// Room boats keep from the islands they sail around.
+const SEA_MARGIN: f32 = 25.;
+
+pub struct Dock {
+    pub island: Entity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoatState {
+    Docked,
+    Sailing(Entity),
+    /// Reached the island it sailed to and waits to unload.
+    Arrived(Entity),
+    Returning,
+}
+
+pub struct Boat {
+    pub dock: Entity,
+    pub state: BoatState,
+    pub passengers: Vec<Entity>,
+    pub cargo: Vec<(NaturalResourceType, u32)>,
+    /// Waypoints still ahead, next first.
+    pub path: VecDeque<Vec2>,
+}
+
+pub fn spawn_dock(
+    commands: &mut Commands,
+    materials: &Materials,
+    config: &GameConfig,
+    island: Entity,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            material: materials.dock_material.clone(),
+            sprite: Sprite::new(BuildingType::Dock.size(config)),
+            transform: Transform::from_translation(vec3(position.x, position.y, 0.)),
+            ..Default::default()
+        })
+        .with(Dock { island })
+        .with(Building(BuildingType::Dock))
+        .current_entity()
+        .unwrap()
+}
+
+fn spawn_boat(
+    commands: &mut Commands,
+    materials: &Materials,
+    owner: Owner,
+    dock: Entity,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            material: materials.boat_material.clone(),
+            sprite: Sprite::new(Vec2::new(16., 6.)),
+            transform: Transform::from_translation(vec3(position.x, position.y, 20.)),
+            ..Default::default()
+        })
+        .with(Boat {
+            dock,
+            state: BoatState::Docked,
+            passengers: Vec::new(),
+            cargo: Vec::new(),
+            path: VecDeque::new(),
+        })
+        .with(owner)
+        .current_entity()
+        .unwrap()
+}
+
+/// Point on an island's edge facing `from`.
+pub fn landing_point(center: Vec2, radius: f32, from: Vec2) -> Vec2 {
+    let offset = from - center;
+    if offset.length() > 0. {
+        center + offset / offset.length() * radius
+    } else {
+        center + Vec2::new(radius, 0.)
+    }
+}
+
+/// Waypoints from `from` to `to` that go around every island in the way. Islands the trip
+/// starts or ends on don't count.
+pub fn water_path(from: Vec2, to: Vec2, islands: &[(Vec2, f32)]) -> VecDeque<Vec2> {
+    let direction = to - from;
+    let length = direction.length();
+    let mut detours: Vec<(f32, Vec2)> = Vec::new();
+    if length > 0. {
+        let along = direction / length;
+        let normal = Vec2::new(-along.y, along.x);
+        for (center, radius) in islands.iter() {
+            let clearance = radius + SEA_MARGIN;
+            if (from - *center).length() < clearance || (to - *center).length() < clearance {
+                continue;
+            }
+            let t = (*center - from).dot(along);
+            let side = (*center - from).dot(normal);
+            if t <= 0. || t >= length || side.abs() >= clearance {
+                continue;
+            }
+            // pass the island on the side the straight line was already closer to
+            let away = if side > 0. { -normal } else { normal };
+            detours.push((t, *center + away * clearance));
+        }
+    }
+    detours.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    detours
+        .into_iter()
+        .map(|(_, waypoint)| waypoint)
+        .chain(std::iter::once(to))
+        .collect()
+}
+
+/// Builds boats and sends them out for any faction, checking the request like the player's.
+fn handle_boat_commands(
+    commands: &mut Commands,
+    events: Res<Events<GameCommand>>,
+    mut event_reader: Local<EventReader<GameCommand>>,
+    materials: Res<Materials>,
+    config: Res<GameConfig>,
+    docks_query: Query<(&Dock, &GlobalTransform, &Owner)>,
+    islands_query: Query<(&GlobalTransform, &IslandSize, &Owner), With<Island>>,
+    houses_query: Query<&House>,
+    mut stock_query: Query<&mut IslandNR>,
+    mut boats_query: Query<(&Owner, &mut Boat)>,
+    mut people_query: Query<(
+        &Owner,
+        &mut Person,
+        &mut TargetPosition,
+        &mut OrderQueue,
+        &mut Visible,
+    )>,
+) {
+    for ev in event_reader.iter(&events) {
+        match ev {
+            GameCommand::BuildBoat { faction, dock } => {
+                let (dock_info, transform, owner) = match docks_query.get(*dock) {
+                    Ok(found) => found,
+                    Err(_) => continue,
+                };
+                if owner.0 != Some(*faction) {
+                    println!("Faction {:?} can't build boats at {:?}", faction, dock);
+                    continue;
+                }
+                let cost = vec![(NaturalResourceType::Wood, config.boat_wood_cost)];
+                match stock_query.get_mut(dock_info.island) {
+                    Ok(mut stock) if can_afford(&stock, &cost) => pay(&mut stock, &cost),
+                    _ => {
+                        println!("Faction {:?} can't afford a boat", faction);
+                        continue;
+                    }
+                }
+                spawn_boat(
+                    commands,
+                    &materials,
+                    *owner,
+                    *dock,
+                    transform.translation.truncate(),
+                );
+            }
+            GameCommand::Sail {
+                faction,
+                boat,
+                island,
+                passengers,
+                cargo,
+            } => {
+                let (boat_owner, mut boat_info) = match boats_query.get_mut(*boat) {
+                    Ok(found) => found,
+                    Err(_) => continue,
+                };
+                if boat_owner.0 != Some(*faction) || boat_info.state != BoatState::Docked {
+                    println!("Boat {:?} can't sail for faction {:?}", boat, faction);
+                    continue;
+                }
+                let (dock, dock_transform, _) = match docks_query.get(boat_info.dock) {
+                    Ok(found) => found,
+                    Err(_) => continue,
+                };
+                let (island_transform, island_size, island_owner) = match islands_query.get(*island)
+                {
+                    Ok(found) if *island != dock.island => found,
+                    _ => continue,
+                };
+                let settling = match island_owner.0 {
+                    None => true,
+                    Some(owner) if owner == *faction => false,
+                    Some(_) => {
+                        println!("Faction {:?} can't land on {:?}", faction, island);
+                        continue;
+                    }
+                };
+
+                // only people living by the dock can board
+                let aboard: Vec<Entity> = passengers
+                    .iter()
+                    .copied()
+                    .filter(|passenger| {
+                        people_query.get_mut(*passenger).map_or(
+                            false,
+                            |(owner, person, _, _, _)| {
+                                owner.0 == Some(*faction)
+                                    && !matches!(person.task, PersonTask::Sailing(_))
+                                    && person
+                                        .house
+                                        .and_then(|house| houses_query.get(house).ok())
+                                        .map_or(false, |house| house.island == dock.island)
+                            },
+                        )
+                    })
+                    .take(BOAT_CAPACITY)
+                    .collect();
+                let house_cost = BuildingType::House.cost(&config);
+                let loaded = IslandNR(cargo.iter().copied().collect());
+                if settling && (aboard.is_empty() || !can_afford(&loaded, &house_cost)) {
+                    println!(
+                        "Settling needs people on board and {} for their first house",
+                        format_cost(&house_cost)
+                    );
+                    continue;
+                }
+                match stock_query.get_mut(dock.island) {
+                    Ok(mut stock) if can_afford(&stock, cargo) => pay(&mut stock, cargo),
+                    _ => {
+                        println!("Faction {:?} doesn't have that cargo", faction);
+                        continue;
+                    }
+                }
+
+                for passenger in aboard.iter() {
+                    if let Ok((_, mut person, mut target, mut orders, mut visible)) =
+                        people_query.get_mut(*passenger)
+                    {
+                        person.task = PersonTask::Sailing(*boat);
+                        target.0 = None;
+                        orders.0.clear();
+                        visible.is_visible = false;
+                    }
+                }
+                let from = dock_transform.translation.truncate();
+                let landing = landing_point(
+                    island_transform.translation.truncate(),
+                    island_size.width / 2.,
+                    from,
+                );
+                let obstacles: Vec<(Vec2, f32)> = islands_query
+                    .iter()
+                    .map(|(transform, size, _)| (transform.translation.truncate(), size.width / 2.))
+                    .collect();
+                boat_info.passengers = aboard;
+                boat_info.cargo = cargo.clone();
+                boat_info.path = water_path(from, landing, &obstacles);
+                boat_info.state = BoatState::Sailing(*island);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn sail_boats(
+    time: Res<GameTime>,
+    config: Res<GameConfig>,
+    mut boats_query: Query<(&mut Transform, &mut Boat)>,
+) {
+    let step = time.delta_seconds() * config.boat_speed;
+    for (mut transform, mut boat) in boats_query.iter_mut() {
+        let next = match boat.path.front() {
+            Some(next) => *next,
+            None => continue,
+        };
+        let offset = next - transform.translation.truncate();
+        if offset.length() > step {
+            transform.translation += (offset / offset.length() * step).extend(0.);
+            continue;
+        }
+        transform.translation.x = next.x;
+        transform.translation.y = next.y;
+        boat.path.pop_front();
+        if boat.path.is_empty() {
+            boat.state = match boat.state {
+                BoatState::Sailing(island) => BoatState::Arrived(island),
+                BoatState::Returning => BoatState::Docked,
+                other => other,
+            };
+        }
+    }
+}
+
+/// Keeps people on board where their boat is, so everything else sees them move with it.
+fn carry_passengers(
+    boats_query: Query<(&GlobalTransform, &Boat)>,
+    mut people_query: Query<&mut Transform, With<Person>>,
+) {
+    for (boat_transform, boat) in boats_query.iter() {
+        for passenger in boat.passengers.iter() {
+            if let Ok(mut transform) = people_query.get_mut(*passenger) {
+                transform.translation.x = boat_transform.translation.x;
+                transform.translation.y = boat_transform.translation.y;
+            }
+        }
+    }
+}
+
+/// Lets everyone off. Landing passengers move into `homes` in turn, and whoever is left over is
+/// homeless until `rehouse_homeless` finds them room. Without `homes` they keep the ones they had.
+fn disembark(people_query: &mut Query<&mut Person>, boat: &mut Boat, homes: Option<&[Entity]>) {
+    for (i, passenger) in boat.passengers.drain(..).enumerate() {
+        if let Ok(mut person) = people_query.get_mut(passenger) {
+            if let Some(homes) = homes {
+                person.house = homes.get(i).copied();
+            }
+            // shown again by `schedule_people` once ashore
+            person.task = PersonTask::Idle;
+        }
+    }
+}
+
+/// Unloads boats that arrived, founding a settlement if the island is still unclaimed, and
+/// sends them back to their dock. Voyages that can't land come back with everyone on board.
+fn unload_boats(
+    commands: &mut Commands,
+    materials: Res<Materials>,
+    config: Res<GameConfig>,
+    factions: Res<Factions>,
+    mut names: ResMut<NameGenerator>,
+    mut world_rng: ResMut<WorldRng>,
+    mut game_events: ResMut<Events<GameEvent>>,
+    mut boats_query: Query<(&GlobalTransform, &Owner, &mut Boat)>,
+    docks_query: Query<(&Dock, &GlobalTransform)>,
+    islands_query: Query<(&GlobalTransform, &IslandSize, &Owner), With<Island>>,
+    houses_query: Query<(Entity, &House)>,
+    buildings_query: Query<(&GlobalTransform, &Sprite), With<Building>>,
+    mut stock_query: Query<&mut IslandNR>,
+    mut people_query: Query<&mut Person>,
+) {
+    let rng = &mut world_rng.0;
+    // residents `sync_residents` hasn't counted yet, when several boats land at once
+    let mut moved_in: HashMap<Entity, usize> = HashMap::default();
+    for (boat_transform, boat_owner, mut boat) in boats_query.iter_mut() {
+        let position = boat_transform.translation.truncate();
+        let (dock, dock_transform) = match docks_query.get(boat.dock) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+
+        let island = match boat.state {
+            BoatState::Arrived(island) => island,
+            BoatState::Docked if !boat.passengers.is_empty() || !boat.cargo.is_empty() => {
+                // back from a voyage that couldn't land
+                if let Ok(mut stock) = stock_query.get_mut(dock.island) {
+                    for (nr_type, amount) in boat.cargo.drain(..) {
+                        *stock.0.entry(nr_type).or_insert(0) += amount;
+                    }
+                }
+                disembark(&mut people_query, &mut boat, None);
+                continue;
+            }
+            _ => continue,
+        };
+        let (island_transform, island_size, island_owner) = match islands_query.get(island) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        let obstacles: Vec<(Vec2, f32)> = islands_query
+            .iter()
+            .map(|(transform, size, _)| (transform.translation.truncate(), size.width / 2.))
+            .collect();
+        boat.path = water_path(position, dock_transform.translation.truncate(), &obstacles);
+        boat.state = BoatState::Returning;
+
+        let homes = match island_owner.0 {
+            None => {
+                let buildings: Vec<(Vec2, Vec2)> = buildings_query
+                    .iter()
+                    .map(|(transform, sprite)| (transform.translation.truncate(), sprite.size))
+                    .collect();
+                let house_position = match find_free_spot(
+                    rng,
+                    BuildingType::House,
+                    &config,
+                    island,
+                    island_transform.translation.truncate(),
+                    island_size.width / 2.,
+                    &buildings,
+                ) {
+                    Some(position) => position,
+                    None => {
+                        // the settlers sail home with their wood, like a voyage that can't land
+                        println!("No room on the island for a first house");
+                        continue;
+                    }
+                };
+                let mut cargo = IslandNR(boat.cargo.iter().copied().collect());
+                pay(&mut cargo, &BuildingType::House.cost(&config));
+                boat.cargo = cargo.0.into_iter().collect();
+
+                let family = boat
+                    .passengers
+                    .first()
+                    .and_then(|passenger| people_query.get_mut(*passenger).ok())
+                    .and_then(|person| person.house)
+                    .and_then(|house| houses_query.get(house).ok())
+                    .map_or_else(|| names.family_name(), |(_, house)| house.family.clone());
+                let house = spawn_house(
+                    commands,
+                    &materials,
+                    &config,
+                    island,
+                    family,
+                    house_position,
+                );
+                commands.insert_one(island, *boat_owner);
+                game_events.send(GameEvent::HouseBuilt {
+                    house,
+                    island,
+                    position: house_position,
+                });
+                game_events.send(GameEvent::IslandSettled {
+                    island,
+                    faction: factions.name_of(*boat_owner),
+                    position: house_position,
+                });
+                vec![house; HouseTier::Hut.capacity()]
+            }
+            owner if owner == boat_owner.0 => {
+                // one entry per free bed, in random order
+                let mut beds: Vec<Entity> = Vec::new();
+                for (entity, house) in houses_query
+                    .iter()
+                    .filter(|(_, house)| house.island == island)
+                {
+                    let taken = house.residents.len() + moved_in.get(&entity).copied().unwrap_or(0);
+                    let free = house.capacity().saturating_sub(taken);
+                    beds.extend(std::iter::repeat(entity).take(free));
+                }
+                beds.shuffle(rng);
+                beds
+            }
+            // taken by someone else while we were at sea
+            _ => continue,
+        };
+
+        if let Ok(mut stock) = stock_query.get_mut(island) {
+            for (nr_type, amount) in boat.cargo.drain(..) {
+                *stock.0.entry(nr_type).or_insert(0) += amount;
+            }
+        }
+        for home in homes.iter().take(boat.passengers.len()) {
+            *moved_in.entry(*home).or_insert(0) += 1;
+        }
+        disembark(&mut people_query, &mut boat, Some(&homes));
+    }
+}
+
+/// `N` builds a boat at the selected island's dock, `V` sends the selected people by boat to
+/// the island under the cursor, with wood for a first house if it is unclaimed.
+fn boat_controls(
+    keys: Res<Input<KeyCode>>,
+    cursor: Res<CursorPosition>,
+    config: Res<GameConfig>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    selected_island: Res<SelectedIsland>,
+    selected: Res<Selected>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+    docks_query: Query<(Entity, &Dock, &Owner)>,
+    boats_query: Query<(Entity, &Boat)>,
+    islands_query: Query<(Entity, &GlobalTransform, &IslandSize, &Owner), With<Island>>,
+    people_query: Query<&Person>,
+    houses_query: Query<&House>,
+) {
+    let mine = Owner(Some(factions.player));
+    let player_dock_on = |island: Entity| {
+        docks_query
+            .iter()
+            .find(|(_, dock, owner)| dock.island == island && **owner == mine)
+            .map(|(entity, _, _)| entity)
+    };
+
+    if keys.just_pressed(KeyCode::N) {
+        match selected_island.0.and_then(player_dock_on) {
+            Some(dock) => game_commands.send(GameCommand::BuildBoat {
+                faction: factions.player,
+                dock,
+            }),
+            None => println!("Select one of your islands with a dock to build a boat"),
+        }
+    }
+
+    if !keys.just_pressed(KeyCode::V) {
+        return;
+    }
+    // boats can only be sent to islands the player has discovered
+    let destination = islands_query.iter().find(|(island, transform, size, _)| {
+        fog.knows_island(factions.player, *island)
+            && (transform.translation.truncate() - cursor.world).length() <= size.width / 2.
+    });
+    let (destination, _, _, destination_owner) = match destination {
+        Some(found) => found,
+        None => return,
+    };
+    let from_island = selected
+        .items
+        .iter()
+        .filter_map(|person| people_query.get(*person).ok())
+        .filter_map(|person| person.house)
+        .filter_map(|house| houses_query.get(house).ok())
+        .map(|house| house.island)
+        .next();
+    let boat = from_island.and_then(|island| {
+        boats_query
+            .iter()
+            .find(|(_, boat)| {
+                boat.state == BoatState::Docked
+                    && docks_query
+                        .get(boat.dock)
+                        .map_or(false, |(_, dock, owner)| {
+                            dock.island == island && *owner == mine
+                        })
+            })
+            .map(|(entity, _)| entity)
+    });
+    let boat = match boat {
+        Some(boat) => boat,
+        None => {
+            println!("No boat waiting at a dock on the selected people's island");
+            return;
+        }
+    };
+    let cargo = if destination_owner.0.is_none() {
+        BuildingType::House.cost(&config)
+    } else {
+        Vec::new()
+    };
+    game_commands.send(GameCommand::Sail {
+        faction: factions.player,
+        boat,
+        island: destination,
+        passengers: selected.items.clone(),
+        cargo,
+    });
+}
+
+pub struct BoatPlugin;
+impl Plugin for BoatPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_game_system(boat_controls.system())
+            .add_game_system(handle_boat_commands.system())
+            .add_game_system(sail_boats.system())
+            .add_game_system(carry_passengers.system())
+            .add_game_system(unload_boats.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::Stage;
+
+    use super::*;
+    use crate::{
+        building::handle_build_commands,
+        faction::{inherit_owners, FactionId},
+    };
+
+    fn wood(world: &World, island: Entity) -> u32 {
+        world.get::<IslandNR>(island).unwrap().0[&NaturalResourceType::Wood]
+    }
+
+    #[test]
+    fn builds_a_dock_and_then_a_boat() {
+        let config = GameConfig::default();
+        let faction = FactionId(0);
+        let mut world = World::new();
+        let mut resources = Resources::default();
+        let island = world.spawn((
+            Island,
+            GlobalTransform::default(),
+            IslandSize {
+                width: 100.,
+                height: 100.,
+            },
+            Owner(Some(faction)),
+            IslandNR([(NaturalResourceType::Wood, 20)].iter().copied().collect()),
+        ));
+        resources.insert(config.clone());
+        resources.insert(Materials::default());
+        resources.insert(Factions::default());
+        resources.insert(NameGenerator::new(Some(1)));
+        resources.insert(Events::<GameEvent>::default());
+        resources.insert(Events::<GameCommand>::default());
+        let mut stage = SystemStage::serial()
+            .with_system(handle_build_commands.system())
+            .with_system(inherit_owners.system())
+            .with_system(handle_boat_commands.system());
+
+        // on the shore of the island, 50 units in radius
+        resources
+            .get_mut::<Events<GameCommand>>()
+            .unwrap()
+            .send(GameCommand::Build {
+                faction,
+                building: BuildingType::Dock,
+                position: Vec2::new(38., 0.),
+            });
+        stage.run(&mut world, &mut resources);
+        stage.run(&mut world, &mut resources);
+        let docks: Vec<(Entity, Entity)> = world
+            .query::<(Entity, &Dock)>()
+            .map(|(entity, dock)| (entity, dock.island))
+            .collect();
+        let dock = match docks.as_slice() {
+            [(dock, dock_island)] if *dock_island == island => *dock,
+            _ => panic!("expected one dock on the island, got {:?}", docks),
+        };
+        assert_eq!(*world.get::<Owner>(dock).unwrap(), Owner(Some(faction)));
+        assert_eq!(wood(&world, island), 20 - config.dock_wood_cost);
+
+        resources
+            .get_mut::<Events<GameCommand>>()
+            .unwrap()
+            .send(GameCommand::BuildBoat { faction, dock });
+        stage.run(&mut world, &mut resources);
+        let boats: Vec<(Entity, Owner)> = world
+            .query::<(&Boat, &Owner)>()
+            .map(|(boat, owner)| (boat.dock, *owner))
+            .collect();
+        assert_eq!(boats, vec![(dock, Owner(Some(faction)))]);
+        assert_eq!(
+            wood(&world, island),
+            20 - config.dock_wood_cost - config.boat_wood_cost
+        );
+    }
+}
@@ -0,0 +1,718 @@
+use bevy::{math::vec3, prelude::*};
+
+use crate::{
+    boat::{spawn_dock, Boat, BoatState, Dock, COAST_BAND},
+    command::GameCommand,
+    config::GameConfig,
+    faction::{Factions, Owner},
+    feed::GameEvent,
+    house::{spawn_house, House},
+    island::{Island, IslandGoods, IslandNR, Size as IslandSize},
+    layout::BUILDING_SPACING,
+    names::NameGenerator,
+    person::{spawn_person, Person, PersonTask},
+    resource::NaturalResourceType,
+    ui::{text_bundle, UiFocus, UiFont},
+    weather::{spawn_windbreak, Windbreak},
+    workshop::{give_stock, spawn_workshop, Ware, Workshop, WorkshopType},
+    CursorPosition, GameStates, Materials, MyStages,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildingType {
+    House,
+    Dock,
+    Workshop(WorkshopType),
+    /// Shelters nearby houses from storms.
+    Windbreak,
+}
+
+impl BuildingType {
+    pub const ALL: [Self; 7] = [
+        Self::House,
+        Self::Dock,
+        Self::Workshop(WorkshopType::Sawmill),
+        Self::Workshop(WorkshopType::Smithy),
+        Self::Workshop(WorkshopType::Farm),
+        Self::Workshop(WorkshopType::Kitchen),
+        Self::Windbreak,
+    ];
+
+    pub fn name(self) -> String {
+        match self {
+            BuildingType::Workshop(kind) => format!("{:?}", kind),
+            _ => format!("{:?}", self),
+        }
+    }
+
+    pub fn cost(self, config: &GameConfig) -> Vec<(NaturalResourceType, u32)> {
+        match self {
+            BuildingType::House => vec![(NaturalResourceType::Wood, config.house_wood_cost)],
+            BuildingType::Dock => vec![(NaturalResourceType::Wood, config.dock_wood_cost)],
+            BuildingType::Workshop(kind) => kind.cost(),
+            BuildingType::Windbreak => vec![(NaturalResourceType::Wood, 4)],
+        }
+    }
+
+    pub fn size(self, config: &GameConfig) -> Vec2 {
+        match self {
+            BuildingType::House => Vec2::new(config.house_size.0, config.house_size.1),
+            BuildingType::Dock => Vec2::new(16., 8.),
+            BuildingType::Workshop(_) => Vec2::new(14., 14.),
+            BuildingType::Windbreak => Vec2::new(24., 6.),
+        }
+    }
+
+    /// Whether the building has to stand on the shore.
+    pub fn coastal(self) -> bool {
+        self == BuildingType::Dock
+    }
+}
+
+/// Marks every placed building with its type, so placement can check footprints.
+pub struct Building(pub BuildingType);
+
+/// Share of a building's cost paid back when it is demolished.
+const REFUND_SHARE: f32 = 0.5;
+
+pub fn can_afford(stock: &IslandNR, cost: &[(NaturalResourceType, u32)]) -> bool {
+    cost.iter()
+        .all(|(nr_type, amount)| stock.0.get(nr_type).copied().unwrap_or(0) >= *amount)
+}
+
+pub fn pay(stock: &mut IslandNR, cost: &[(NaturalResourceType, u32)]) {
+    for (nr_type, amount) in cost {
+        if let Some(count) = stock.0.get_mut(nr_type) {
+            *count = count.saturating_sub(*amount);
+        }
+    }
+}
+
+pub fn format_cost(cost: &[(NaturalResourceType, u32)]) -> String {
+    cost.iter()
+        .map(|(nr_type, amount)| format!("{} {:?}", amount, nr_type))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    Valid(Entity),
+    OffIsland,
+    NotCoastal,
+    Overlapping,
+    NotOwned,
+    TooExpensive,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement::OffIsland
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BuildMode {
+    pub selected: Option<BuildingType>,
+}
+
+#[derive(Debug, Default)]
+pub struct BuildPreview {
+    pub position: Vec2,
+    pub placement: Placement,
+}
+
+pub struct BuildGhost(pub BuildingType);
+pub struct BuildModeText;
+
+pub struct BuildMaterials {
+    pub valid: Handle<ColorMaterial>,
+    pub invalid: Handle<ColorMaterial>,
+}
+
+fn setup_build_mode(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let mut text = text_bundle(&font, "", 16.);
+    text.style.position_type = PositionType::Absolute;
+    text.style.position = Rect {
+        left: Val::Px(10.),
+        bottom: Val::Px(10.),
+        ..Default::default()
+    };
+    text.visible.is_visible = false;
+
+    commands
+        .spawn(text)
+        .with(BuildModeText)
+        .insert_resource(BuildMaterials {
+            valid: color_materials.add(Color::rgba(0.3, 0.5, 1., 0.6).into()),
+            invalid: color_materials.add(Color::rgba(1., 0.1, 0.1, 0.6).into()),
+        });
+}
+
+fn toggle_build_mode(keys: Res<Input<KeyCode>>, mut mode: ResMut<BuildMode>) {
+    if keys.just_pressed(KeyCode::Escape) {
+        mode.selected = None;
+    }
+    if keys.just_pressed(KeyCode::B) {
+        mode.selected = match mode.selected {
+            Some(_) => None,
+            None => Some(BuildingType::ALL[0]),
+        };
+    }
+    let number_keys = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+    ];
+    if mode.selected.is_some() {
+        for (key, building) in number_keys.iter().zip(BuildingType::ALL.iter()) {
+            if keys.just_pressed(*key) {
+                mode.selected = Some(*building);
+            }
+        }
+    }
+}
+
+/// An island as seen by whoever is placing a building.
+#[derive(Debug, Clone, Copy)]
+pub struct IslandSite {
+    pub island: Entity,
+    pub center: Vec2,
+    pub radius: f32,
+    pub owned: bool,
+    pub can_afford: bool,
+}
+
+/// Finds where the selected building would go and whether it can be placed there.
+pub fn check_placement(
+    building: BuildingType,
+    position: Vec2,
+    config: &GameConfig,
+    islands: impl Iterator<Item = IslandSite>,
+    buildings: impl Iterator<Item = (Vec2, Vec2)>,
+) -> Placement {
+    let size = building.size(config);
+    let half_diagonal = size.length() / 2.;
+    let site = islands
+        .into_iter()
+        .find(|site| (position - site.center).length() + half_diagonal <= site.radius);
+    let site = match site {
+        Some(site) => site,
+        None => return Placement::OffIsland,
+    };
+    if building.coastal()
+        && site.radius - (position - site.center).length() > COAST_BAND + half_diagonal
+    {
+        return Placement::NotCoastal;
+    }
+
+    let overlapping = buildings.into_iter().any(|(other_position, other_size)| {
+        let reach = (size + other_size) / 2. + Vec2::splat(BUILDING_SPACING);
+        (position.x - other_position.x).abs() < reach.x
+            && (position.y - other_position.y).abs() < reach.y
+    });
+    if overlapping {
+        Placement::Overlapping
+    } else if !site.owned {
+        Placement::NotOwned
+    } else if !site.can_afford {
+        Placement::TooExpensive
+    } else {
+        Placement::Valid(site.island)
+    }
+}
+
+fn update_build_preview(
+    commands: &mut Commands,
+    mode: Res<BuildMode>,
+    cursor: Res<CursorPosition>,
+    config: Res<GameConfig>,
+    factions: Res<Factions>,
+    build_materials: Res<BuildMaterials>,
+    mut preview: ResMut<BuildPreview>,
+    islands_query: Query<(Entity, &GlobalTransform, &IslandSize, &Owner, &IslandNR), With<Island>>,
+    buildings_query: Query<(&GlobalTransform, &Sprite), With<Building>>,
+    mut ghost_query: Query<(
+        Entity,
+        &BuildGhost,
+        &mut Transform,
+        &mut Handle<ColorMaterial>,
+    )>,
+    mut text_query: Query<(&mut Text, &mut Visible), With<BuildModeText>>,
+) {
+    let building = match mode.selected {
+        Some(building) => building,
+        None => {
+            for (ghost, _, _, _) in ghost_query.iter_mut() {
+                commands.despawn(ghost);
+            }
+            for (_, mut visible) in text_query.iter_mut() {
+                visible.is_visible = false;
+            }
+            return;
+        }
+    };
+
+    let cost = building.cost(&config);
+    preview.position = cursor.world;
+    preview.placement = check_placement(
+        building,
+        cursor.world,
+        &config,
+        islands_query
+            .iter()
+            .map(|(island, transform, size, owner, stock)| IslandSite {
+                island,
+                center: transform.translation.truncate(),
+                radius: size.width / 2.,
+                owned: owner.0 == Some(factions.player),
+                can_afford: can_afford(stock, &cost),
+            }),
+        buildings_query
+            .iter()
+            .map(|(transform, sprite)| (transform.translation.truncate(), sprite.size)),
+    );
+    let material = match preview.placement {
+        Placement::Valid(_) => build_materials.valid.clone(),
+        _ => build_materials.invalid.clone(),
+    };
+
+    let mut has_ghost = false;
+    for (ghost, ghost_building, mut transform, mut ghost_material) in ghost_query.iter_mut() {
+        if ghost_building.0 != building {
+            commands.despawn(ghost);
+            continue;
+        }
+        has_ghost = true;
+        transform.translation = vec3(cursor.world.x, cursor.world.y, 50.);
+        if *ghost_material != material {
+            *ghost_material = material.clone();
+        }
+    }
+    if !has_ghost {
+        commands
+            .spawn(SpriteBundle {
+                material,
+                sprite: Sprite::new(building.size(&config)),
+                transform: Transform::from_translation(vec3(cursor.world.x, cursor.world.y, 50.)),
+                ..Default::default()
+            })
+            .with(BuildGhost(building));
+    }
+
+    let status = match preview.placement {
+        Placement::Valid(_) => "click to place",
+        Placement::OffIsland => "must be on an island",
+        Placement::NotCoastal => "must be on the shore",
+        Placement::Overlapping => "too close to another building",
+        Placement::NotOwned => "your faction doesn't own this island",
+        Placement::TooExpensive => "this island can't afford it",
+    };
+    let value = format!(
+        "Build {} ({}): {} [B/Esc to stop]",
+        building.name(),
+        format_cost(&cost),
+        status
+    );
+    for (mut text, mut visible) in text_query.iter_mut() {
+        visible.is_visible = true;
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+fn place_building(
+    mouse: Res<Input<MouseButton>>,
+    ui_focus: Res<UiFocus>,
+    mode: Res<BuildMode>,
+    preview: Res<BuildPreview>,
+    factions: Res<Factions>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || ui_focus.hovered {
+        return;
+    }
+    if let (Some(building), Placement::Valid(_)) = (mode.selected, preview.placement) {
+        game_commands.send(GameCommand::Build {
+            faction: factions.player,
+            building,
+            position: preview.position,
+        });
+    }
+}
+
+/// Places buildings any faction asked for, after checking the spot the same way the preview does.
+pub fn handle_build_commands(
+    commands: &mut Commands,
+    events: Res<Events<GameCommand>>,
+    mut event_reader: Local<EventReader<GameCommand>>,
+    materials: Res<Materials>,
+    config: Res<GameConfig>,
+    mut names: ResMut<NameGenerator>,
+    mut game_events: ResMut<Events<GameEvent>>,
+    islands_query: Query<(Entity, &GlobalTransform, &IslandSize, &Owner), With<Island>>,
+    buildings_query: Query<(&GlobalTransform, &Sprite), With<Building>>,
+    mut stock_query: Query<&mut IslandNR>,
+) {
+    // buildings placed this frame aren't in the query yet
+    let mut placed: Vec<(Vec2, Vec2)> = Vec::new();
+    for ev in event_reader.iter(&events) {
+        let (faction, building, position) = match ev {
+            GameCommand::Build {
+                faction,
+                building,
+                position,
+            } => (*faction, *building, *position),
+            _ => continue,
+        };
+        let cost = building.cost(&config);
+        let sites: Vec<IslandSite> = islands_query
+            .iter()
+            .map(|(island, transform, size, owner)| IslandSite {
+                island,
+                center: transform.translation.truncate(),
+                radius: size.width / 2.,
+                owned: owner.0 == Some(faction),
+                can_afford: stock_query
+                    .get_mut(island)
+                    .map_or(false, |stock| can_afford(&stock, &cost)),
+            })
+            .collect();
+        let placement = check_placement(
+            building,
+            position,
+            &config,
+            sites.into_iter(),
+            buildings_query
+                .iter()
+                .map(|(transform, sprite)| (transform.translation.truncate(), sprite.size))
+                .chain(placed.iter().copied()),
+        );
+        let island = match placement {
+            Placement::Valid(island) => island,
+            rejected => {
+                println!(
+                    "Faction {:?} can't build {:?} at {:?}: {:?}",
+                    faction, building, position, rejected
+                );
+                continue;
+            }
+        };
+        if let Ok(mut stock) = stock_query.get_mut(island) {
+            pay(&mut stock, &cost);
+        }
+        placed.push((position, building.size(&config)));
+        spawn_building(
+            commands,
+            &materials,
+            &config,
+            &mut names,
+            &mut game_events,
+            building,
+            island,
+            position,
+        );
+    }
+}
+
+fn spawn_building(
+    commands: &mut Commands,
+    materials: &Materials,
+    config: &GameConfig,
+    names: &mut NameGenerator,
+    game_events: &mut Events<GameEvent>,
+    building: BuildingType,
+    island: Entity,
+    position: Vec2,
+) {
+    match building {
+        BuildingType::House => {
+            let family = names.family_name();
+            let house = spawn_house(
+                commands,
+                materials,
+                config,
+                island,
+                family.clone(),
+                position,
+            );
+            game_events.send(GameEvent::HouseBuilt {
+                house,
+                island,
+                position,
+            });
+            let name = names.person_name(&family);
+            let person = spawn_person(commands, materials, name.clone(), Some(house), position);
+            game_events.send(GameEvent::PersonSpawned {
+                person,
+                name,
+                island: Some(island),
+                position,
+            });
+        }
+        BuildingType::Dock => {
+            spawn_dock(commands, materials, config, island, position);
+        }
+        BuildingType::Workshop(kind) => {
+            spawn_workshop(commands, materials, config, kind, island, position);
+        }
+        BuildingType::Windbreak => {
+            spawn_windbreak(commands, materials, config, island, position);
+        }
+    }
+}
+
+/// `X` demolishes the building under the cursor.
+fn demolish_controls(
+    keys: Res<Input<KeyCode>>,
+    cursor: Res<CursorPosition>,
+    factions: Res<Factions>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+    buildings_query: Query<(Entity, &GlobalTransform, &Sprite), With<Building>>,
+) {
+    if !keys.just_pressed(KeyCode::X) {
+        return;
+    }
+    let hovered = buildings_query.iter().find(|(_, transform, sprite)| {
+        let offset = cursor.world - transform.translation.truncate();
+        offset.x.abs() <= sprite.size.x / 2. && offset.y.abs() <= sprite.size.y / 2.
+    });
+    if let Some((building, _, _)) = hovered {
+        game_commands.send(GameCommand::Demolish {
+            faction: factions.player,
+            building,
+        });
+    }
+}
+
+/// Tears down buildings on the faction's own islands and pays part of their cost, and of any
+/// house upgrades, back. Residents
+/// are left homeless until `rehouse_homeless` finds them a place, workers go idle, and a dock
+/// takes its docked boats with it but waits for any at sea to come back.
+fn handle_demolish_commands(
+    commands: &mut Commands,
+    events: Res<Events<GameCommand>>,
+    mut event_reader: Local<EventReader<GameCommand>>,
+    config: Res<GameConfig>,
+    mut game_events: ResMut<Events<GameEvent>>,
+    buildings_query: Query<(
+        &Building,
+        &GlobalTransform,
+        Option<&House>,
+        Option<&Dock>,
+        Option<&Workshop>,
+        Option<&Windbreak>,
+    )>,
+    boats_query: Query<(Entity, &Boat)>,
+    mut islands_query: Query<(&Owner, &mut IslandNR, &mut IslandGoods), With<Island>>,
+    mut people_query: Query<&mut Person>,
+) {
+    for ev in event_reader.iter(&events) {
+        let (faction, entity) = match ev {
+            GameCommand::Demolish { faction, building } => (*faction, *building),
+            _ => continue,
+        };
+        let (building, transform, house, dock, workshop, windbreak) =
+            match buildings_query.get(entity) {
+                Ok(found) => found,
+                Err(_) => continue,
+            };
+        let island = house
+            .map(|house| house.island)
+            .or_else(|| dock.map(|dock| dock.island))
+            .or_else(|| workshop.map(|workshop| workshop.island))
+            .or_else(|| windbreak.map(|windbreak| windbreak.island));
+        let island = match island {
+            Some(island) => island,
+            None => continue,
+        };
+        let (owner, mut stock, mut goods) = match islands_query.get_mut(island) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        if owner.0 != Some(faction) {
+            continue;
+        }
+        let boats: Vec<(Entity, bool)> = boats_query
+            .iter()
+            .filter(|(_, boat)| boat.dock == entity)
+            .map(|(boat_entity, boat)| (boat_entity, boat.state == BoatState::Docked))
+            .collect();
+        if boats.iter().any(|(_, docked)| !docked) {
+            println!("Can't demolish a dock while its boats are at sea");
+            continue;
+        }
+
+        let mut spent: Vec<(Ware, u32)> = building
+            .0
+            .cost(&config)
+            .into_iter()
+            .map(|(nr_type, amount)| (Ware::Raw(nr_type), amount))
+            .collect();
+        if let Some(house) = house {
+            spent.extend(house.tier.upgrades_cost());
+        }
+        for (ware, amount) in spent {
+            let refund = (amount as f32 * REFUND_SHARE).floor() as u32;
+            give_stock(ware, refund, &mut stock, &mut goods);
+        }
+        for mut person in people_query.iter_mut() {
+            if person.house == Some(entity) {
+                println!("{} lost their home", person.name);
+                person.house = None;
+            }
+            if person.task == PersonTask::Working(entity) {
+                person.task = PersonTask::Idle;
+            }
+        }
+        for (boat, _) in boats {
+            commands.despawn(boat);
+        }
+        commands.despawn_recursive(entity);
+        game_events.send(GameEvent::BuildingDemolished {
+            building: building.0,
+            island,
+            position: transform.translation.truncate(),
+        });
+    }
+}
+
+pub struct BuildingPlugin;
+impl Plugin for BuildingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<BuildMode>()
+            .init_resource::<BuildPreview>()
+            .add_world_gen_system(MyStages::Ui, setup_build_mode.system())
+            .add_game_system(toggle_build_mode.system())
+            .add_game_system(update_build_preview.system())
+            .add_game_system(place_building.system())
+            .add_game_system(handle_build_commands.system())
+            .add_game_system(demolish_controls.system())
+            .add_game_system(handle_demolish_commands.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::Stage;
+
+    use super::*;
+    use crate::{
+        faction::FactionId,
+        house::{rehouse_homeless, HouseTier},
+        island::handle_gather_events,
+        person::GatherEvent,
+        workshop::Good,
+    };
+
+    const FACTION: Owner = Owner(Some(FactionId(0)));
+
+    fn spawn_house_at(world: &mut World, island: Entity, tier: HouseTier) -> Entity {
+        world.spawn((
+            Building(BuildingType::House),
+            GlobalTransform::default(),
+            House {
+                island,
+                family: "Ashby".to_string(),
+                tier,
+                residents: Vec::new(),
+            },
+        ))
+    }
+
+    fn spawn_resident(world: &mut World, house: Entity) -> Entity {
+        world.spawn((
+            Person {
+                house: Some(house),
+                ..Default::default()
+            },
+            Transform::default(),
+            GlobalTransform::default(),
+            FACTION,
+        ))
+    }
+
+    #[test]
+    fn demolishing_a_lived_in_house_leaves_nobody_dangling() {
+        let config = GameConfig::default();
+        let mut world = World::new();
+        let mut resources = Resources::default();
+        let island = world.spawn((
+            Island,
+            GlobalTransform::default(),
+            IslandSize {
+                width: 100.,
+                height: 100.,
+            },
+            FACTION,
+            IslandNR(Default::default()),
+            IslandGoods::default(),
+        ));
+        let cottage = spawn_house_at(&mut world, island, HouseTier::Cottage);
+        let hut = spawn_house_at(&mut world, island, HouseTier::Hut);
+        spawn_resident(&mut world, hut);
+        let evicted = vec![
+            spawn_resident(&mut world, cottage),
+            spawn_resident(&mut world, cottage),
+        ];
+        resources.insert(config.clone());
+        resources.insert(Events::<GameCommand>::default());
+        resources.insert(Events::<GameEvent>::default());
+        resources.insert(Events::<GatherEvent>::default());
+        let mut stage = SystemStage::serial()
+            .with_system(handle_demolish_commands.system())
+            .with_system(handle_gather_events.system())
+            .with_system(rehouse_homeless.system());
+
+        resources
+            .get_mut::<Events<GameCommand>>()
+            .unwrap()
+            .send(GameCommand::Demolish {
+                faction: FactionId(0),
+                building: cottage,
+            });
+        stage.run(&mut world, &mut resources);
+        // the evicted still gather, and their last house is long gone
+        for person in evicted.iter() {
+            resources
+                .get_mut::<Events<GatherEvent>>()
+                .unwrap()
+                .send(GatherEvent(*person, NaturalResourceType::Wood));
+        }
+        stage.run(&mut world, &mut resources);
+
+        assert!(world.get::<House>(cottage).is_err());
+        let houses: Vec<Option<Entity>> = evicted
+            .iter()
+            .map(|person| world.get::<Person>(*person).unwrap().house)
+            .collect();
+        // the hut had one bed left
+        assert!(houses.contains(&Some(hut)));
+        assert!(houses.contains(&None));
+
+        // half the house and half the upgrade to a cottage come back
+        let stock = world.get::<IslandNR>(island).unwrap();
+        let goods = world.get::<IslandGoods>(island).unwrap();
+        let refund = |amount: u32| (amount as f32 * REFUND_SHARE).floor() as u32;
+        let wood = refund(config.house_wood_cost) + refund(2);
+        let gathered = houses.iter().filter(|house| house.is_some()).count() as u32;
+        assert_eq!(
+            stock
+                .0
+                .get(&NaturalResourceType::Wood)
+                .copied()
+                .unwrap_or(0),
+            wood + gathered
+        );
+        assert_eq!(goods.0.get(&Good::Planks).copied(), Some(refund(4)));
+    }
+}
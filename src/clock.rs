@@ -0,0 +1,275 @@
+use bevy::prelude::*;
+
+use crate::{
+    config::GameConfig,
+    faction::{Factions, Owner},
+    fog::{FogOfWar, Sight},
+    house::House,
+    orders::OrderQueue,
+    person::{Person, PersonTask, TargetPosition},
+    resource::{NaturalResourceMaterials, NaturalResourceType},
+    ui::{text_bundle, UiFont},
+    GameStates, GameTime, Materials, MyStages,
+};
+
+const HOURS_PER_DAY: f32 = 24.;
+const START_HOUR: f32 = 7.;
+const DAWN: f32 = 6.;
+const DUSK: f32 = 18.;
+const BEDTIME: f32 = 21.;
+/// How bright the scene gets in the middle of the night.
+const NIGHT_BRIGHTNESS: f32 = 0.35;
+/// Hours it takes to go from dark to light and back.
+const TWILIGHT_HOURS: f32 = 1.5;
+/// People this close to their house are home.
+const HOME_RADIUS: f32 = 4.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DayPhase {
+    /// People work and gather.
+    Day,
+    /// People wander around their homes.
+    Evening,
+    /// People sleep in their houses.
+    Night,
+}
+
+#[derive(Debug)]
+pub struct WorldClock {
+    pub day: u32,
+    /// Hour of the day, from 0 up to 24.
+    pub hour: f32,
+}
+
+impl Default for WorldClock {
+    fn default() -> Self {
+        Self {
+            day: 1,
+            hour: START_HOUR,
+        }
+    }
+}
+
+impl WorldClock {
+    pub fn phase(&self) -> DayPhase {
+        if self.hour < DAWN || self.hour >= BEDTIME {
+            DayPhase::Night
+        } else if self.hour < DUSK {
+            DayPhase::Day
+        } else {
+            DayPhase::Evening
+        }
+    }
+
+    /// 1 in daylight, down to `NIGHT_BRIGHTNESS` at night, fading in between.
+    pub fn brightness(&self) -> f32 {
+        let light = if self.hour < DAWN {
+            0.
+        } else if self.hour < DAWN + TWILIGHT_HOURS {
+            (self.hour - DAWN) / TWILIGHT_HOURS
+        } else if self.hour < DUSK {
+            1.
+        } else if self.hour < DUSK + TWILIGHT_HOURS {
+            1. - (self.hour - DUSK) / TWILIGHT_HOURS
+        } else {
+            0.
+        };
+        NIGHT_BRIGHTNESS + (1. - NIGHT_BRIGHTNESS) * light
+    }
+}
+
+/// Daylight colours of everything `tint_scene` darkens at night.
+#[derive(Default)]
+pub struct SceneColors {
+    materials: Vec<(Handle<ColorMaterial>, Color)>,
+    clear_color: Color,
+    brightness: f32,
+}
+
+pub struct ClockText;
+
+fn setup_clock(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    materials: Res<Materials>,
+    nr_materials: Res<NaturalResourceMaterials>,
+    factions: Res<Factions>,
+    clear_color: Res<ClearColor>,
+    color_materials: Res<Assets<ColorMaterial>>,
+) {
+    let mut text = text_bundle(&font, "", 16.);
+    text.style.position_type = PositionType::Absolute;
+    text.style.position = Rect {
+        left: Val::Percent(40.),
+        top: Val::Px(30.),
+        ..Default::default()
+    };
+    commands.spawn(text).with(ClockText);
+
+    let mut tinted = vec![
+        materials.soil_material.clone(),
+        materials.grass_material.clone(),
+        materials.house_material.clone(),
+        materials.dock_material.clone(),
+        materials.boat_material.clone(),
+        materials.workshop_material.clone(),
+        materials.roof_material.clone(),
+        materials.manor_roof_material.clone(),
+        materials.road_material.clone(),
+        materials.windbreak_material.clone(),
+        materials.skin.clone(),
+        materials.selected_skin.clone(),
+    ];
+    // faction houses and resource nodes darken along with everything else
+    tinted.extend(
+        factions
+            .list
+            .iter()
+            .map(|faction| faction.house_material.clone()),
+    );
+    tinted.extend(
+        NaturalResourceType::ALL
+            .iter()
+            .map(|nr_type| nr_materials.get(*nr_type)),
+    );
+    commands.insert_resource(SceneColors {
+        materials: tinted
+            .into_iter()
+            .filter_map(|handle| {
+                let color = color_materials.get(&handle)?.color;
+                Some((handle, color))
+            })
+            .collect(),
+        clear_color: clear_color.0,
+        brightness: 1.,
+    });
+}
+
+fn advance_clock(time: Res<GameTime>, config: Res<GameConfig>, mut clock: ResMut<WorldClock>) {
+    clock.hour += time.delta_seconds() * HOURS_PER_DAY / config.day_length;
+    if clock.hour >= HOURS_PER_DAY {
+        clock.hour -= HOURS_PER_DAY;
+        clock.day += 1;
+        println!("Day {} begins", clock.day);
+    }
+}
+
+/// Sends people home at night and puts them to bed once they are there, wakes them in the
+/// morning, and ends the working day in the evening. Decides who is shown: everyone awake and
+/// ashore, except other factions' people where the player isn't watching.
+fn schedule_people(
+    clock: Res<WorldClock>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    houses_query: Query<&GlobalTransform, With<House>>,
+    mut people_query: Query<(
+        &GlobalTransform,
+        &mut Person,
+        &mut TargetPosition,
+        &mut Visible,
+        &OrderQueue,
+        Option<&Owner>,
+    )>,
+) {
+    let phase = clock.phase();
+    let player = factions.player;
+    for (transform, mut person, mut target, mut visible, orders, owner) in people_query.iter_mut() {
+        match (phase, &person.task) {
+            // the boat hides its passengers until they land
+            (_, PersonTask::Sailing(_)) => continue,
+            (DayPhase::Night, PersonTask::Sleeping) => continue,
+            (DayPhase::Night, PersonTask::Idle)
+            | (DayPhase::Night, PersonTask::Wandering)
+            | (DayPhase::Night, PersonTask::Working(_))
+                if orders.0.is_empty() =>
+            {
+                let home = match person.house.and_then(|house| houses_query.get(house).ok()) {
+                    Some(home) => home.translation.truncate(),
+                    // the homeless sleep where they are
+                    None => transform.translation.truncate(),
+                };
+                if (home - transform.translation.truncate()).length() <= HOME_RADIUS {
+                    person.task = PersonTask::Sleeping;
+                    target.0 = None;
+                    visible.is_visible = false;
+                    continue;
+                }
+                if person.task != PersonTask::GoingHome {
+                    person.task = PersonTask::GoingHome;
+                    target.0 = Some(home);
+                }
+            }
+            (DayPhase::Day, PersonTask::Sleeping) | (DayPhase::Evening, PersonTask::Sleeping) => {
+                person.task = PersonTask::Idle;
+            }
+            (DayPhase::Evening, PersonTask::Working(_)) => {
+                person.task = PersonTask::Idle;
+            }
+            _ => {}
+        }
+        // anyone woken up by an order or the morning shows up again, strangers only if watched
+        let mine = owner.map_or(false, |owner| owner.0 == Some(player));
+        let shown = person.task != PersonTask::Sleeping
+            && (mine || fog.sight(player, transform.translation.truncate()) == Sight::Watched);
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+    }
+}
+
+/// Darkens every material and the sky by the time of day.
+fn tint_scene(
+    clock: Res<WorldClock>,
+    mut scene: ResMut<SceneColors>,
+    mut clear_color: ResMut<ClearColor>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let brightness = clock.brightness();
+    if (brightness - scene.brightness).abs() < 0.01 {
+        return;
+    }
+    scene.brightness = brightness;
+    let darken = |color: Color| {
+        Color::rgba(
+            color.r() * brightness,
+            color.g() * brightness,
+            color.b() * brightness,
+            color.a(),
+        )
+    };
+    for (handle, color) in scene.materials.iter() {
+        if let Some(material) = color_materials.get_mut(handle) {
+            material.color = darken(*color);
+        }
+    }
+    clear_color.0 = darken(scene.clear_color);
+}
+
+fn update_clock_text(clock: Res<WorldClock>, mut text_query: Query<&mut Text, With<ClockText>>) {
+    let minutes = (clock.hour.fract() * 60.) as u32;
+    let value = format!(
+        "Day {}, {:02}:{:02} ({:?})",
+        clock.day,
+        clock.hour as u32,
+        minutes,
+        clock.phase()
+    );
+    for mut text in text_query.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+pub struct ClockPlugin;
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<WorldClock>()
+            .init_resource::<SceneColors>()
+            .add_world_gen_system(MyStages::Ui, setup_clock.system())
+            .add_game_system(advance_clock.system())
+            .add_game_system(schedule_people.system())
+            .add_game_system(tint_scene.system())
+            .add_game_system(update_clock_text.system());
+    }
+}
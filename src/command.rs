@@ -0,0 +1,79 @@
+use bevy::prelude::*;
+
+use crate::{
+    building::BuildingType, faction::FactionId, orders::Order, resource::NaturalResourceType,
+};
+
+/// Something a faction asks the game to do. Player input and the AI both go through these and
+/// are checked the same way, so the AI can't do anything the player couldn't.
+#[derive(Debug, Clone)]
+pub enum GameCommand {
+    Build {
+        faction: FactionId,
+        building: BuildingType,
+        position: Vec2,
+    },
+    Order {
+        faction: FactionId,
+        people: Vec<Entity>,
+        order: Order,
+        /// Add to the end of each person's queue instead of replacing it.
+        queue: bool,
+    },
+    BuildBoat {
+        faction: FactionId,
+        dock: Entity,
+    },
+    /// Sends a docked boat to `island` with whichever of `passengers` live by its dock. An
+    /// unclaimed island is settled, which needs passengers and wood for a house in `cargo`.
+    Sail {
+        faction: FactionId,
+        boat: Entity,
+        island: Entity,
+        passengers: Vec<Entity>,
+        cargo: Vec<(NaturalResourceType, u32)>,
+    },
+    /// Standing order to ship `resource` from one island to another whenever the source holds
+    /// more than the trade threshold. Both islands need a dock.
+    OpenTradeRoute {
+        faction: FactionId,
+        from: Entity,
+        to: Entity,
+        resource: NaturalResourceType,
+    },
+    CloseTradeRoute {
+        faction: FactionId,
+        route: usize,
+    },
+    /// Lays a straight road between two points on the same island, paid in Wood by its length.
+    BuildRoad {
+        faction: FactionId,
+        island: Entity,
+        from: Vec2,
+        to: Vec2,
+    },
+    /// Tears down one of the faction's buildings for a partial refund.
+    Demolish {
+        faction: FactionId,
+        building: Entity,
+    },
+    /// Moves a house up to its next tier, paid for by its island.
+    UpgradeHouse {
+        faction: FactionId,
+        house: Entity,
+    },
+    /// Buys (positive `amount`) or sells (negative) a resource at an island's market for Gold.
+    MarketTrade {
+        faction: FactionId,
+        island: Entity,
+        resource: NaturalResourceType,
+        amount: i32,
+    },
+}
+
+pub struct GameCommandPlugin;
+impl Plugin for GameCommandPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<GameCommand>();
+    }
+}
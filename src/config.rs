@@ -0,0 +1,324 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::ai::AiDifficulty;
+
+const CONFIG_PATH: &str = "assets/config.ron";
+const RELOAD_CHECK_INTERVAL: f32 = 1.;
+/// Bounds on `resource_rarity`, so ore weights stay finite and ore never disappears entirely.
+const MIN_RARITY: f32 = 0.1;
+const MAX_RARITY: f32 = 10.;
+
+/// Gameplay tunables, loaded from `assets/config.ron`. Missing fields keep their defaults.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    pub person_speed: f32,
+    pub camera_speed: f32,
+    /// Seconds between idle people picking a new spot to wander to.
+    pub wander_interval: f32,
+    /// Seconds between people looking for the nearest resource.
+    pub gather_interval: f32,
+    pub wander_radius: f32,
+    pub house_size: (f32, f32),
+    /// Wood an island spends on each new house.
+    pub house_wood_cost: u32,
+    /// Whether islands spend their wood on houses by themselves.
+    pub auto_build_houses: bool,
+    /// Range of resource groves and veins planted around each island, upper bound exclusive.
+    pub resource_clusters: (usize, usize),
+    /// How much scarcer Coal, Iron and Gold are than an island's biome makes them: at 2 ore
+    /// clusters and gold veins are half as likely. Between 0.1 and 10.
+    pub resource_rarity: f32,
+    /// Wood an island spends on a dock.
+    pub dock_wood_cost: u32,
+    /// Wood an island spends on a boat.
+    pub boat_wood_cost: u32,
+    pub boat_speed: f32,
+    /// Stock a trade route leaves on its source island.
+    pub trade_threshold: u32,
+    /// Most a trade route ships at once.
+    pub trade_batch: u32,
+    /// Number of islands in the archipelago. Only read at startup.
+    pub islands: usize,
+    /// Range of houses each home island starts with, upper bound exclusive.
+    pub starting_houses: (usize, usize),
+    /// Number of factions, the first one is the player's. Only read at startup.
+    pub factions: usize,
+    /// How well computer-controlled factions play: `Easy`, `Normal` or `Hard`.
+    pub ai_difficulty: AiDifficulty,
+    /// Real seconds in one in-game day.
+    pub day_length: f32,
+    /// Seed for world generation, random when omitted. Only read at startup.
+    pub seed: Option<u64>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            person_speed: 200.,
+            camera_speed: 10.,
+            wander_interval: 2.,
+            gather_interval: 1.,
+            wander_radius: 100.,
+            house_size: (10., 10.),
+            house_wood_cost: 2,
+            auto_build_houses: true,
+            resource_clusters: (3, 7),
+            resource_rarity: 1.,
+            dock_wood_cost: 4,
+            boat_wood_cost: 5,
+            boat_speed: 120.,
+            trade_threshold: 10,
+            trade_batch: 5,
+            islands: 5,
+            starting_houses: (1, 10),
+            factions: 2,
+            ai_difficulty: AiDifficulty::Normal,
+            day_length: 240.,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(ron::Error),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "could not parse config: {}", e),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid config values:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn from_ron(source: &str) -> Result<Self, ConfigError> {
+        let config: GameConfig = ron::de::from_str(source).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let source = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::from_ron(&source)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        let mut positive = |name: &str, value: f32| {
+            if !(value.is_finite() && value > 0.) {
+                problems.push(format!("{} must be a positive number, got {}", name, value));
+            }
+        };
+        positive("person_speed", self.person_speed);
+        positive("camera_speed", self.camera_speed);
+        positive("wander_interval", self.wander_interval);
+        positive("gather_interval", self.gather_interval);
+        positive("wander_radius", self.wander_radius);
+        positive("house_size.0", self.house_size.0);
+        positive("house_size.1", self.house_size.1);
+        positive("boat_speed", self.boat_speed);
+        positive("day_length", self.day_length);
+
+        if self.house_wood_cost == 0 {
+            problems.push("house_wood_cost must be at least 1".to_string());
+        }
+        if self.dock_wood_cost == 0 {
+            problems.push("dock_wood_cost must be at least 1".to_string());
+        }
+        if self.boat_wood_cost == 0 {
+            problems.push("boat_wood_cost must be at least 1".to_string());
+        }
+        if self.trade_batch == 0 {
+            problems.push("trade_batch must be at least 1".to_string());
+        }
+        if self.islands == 0 {
+            problems.push("islands must be at least 1".to_string());
+        }
+        let (min_clusters, max_clusters) = self.resource_clusters;
+        if min_clusters >= max_clusters {
+            problems.push(format!(
+                "resource_clusters must be a non-empty range (min, max), got ({}, {})",
+                min_clusters, max_clusters
+            ));
+        }
+        let (min_houses, max_houses) = self.starting_houses;
+        if min_houses >= max_houses {
+            problems.push(format!(
+                "starting_houses must be a non-empty range (min, max), got ({}, {})",
+                min_houses, max_houses
+            ));
+        }
+        if !(MIN_RARITY..=MAX_RARITY).contains(&self.resource_rarity) {
+            problems.push(format!(
+                "resource_rarity must be between {} and {}, got {}",
+                MIN_RARITY, MAX_RARITY, self.resource_rarity
+            ));
+        }
+        if self.factions == 0 || self.factions > 6 {
+            problems.push(format!(
+                "factions must be between 1 and 6, got {}",
+                self.factions
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+/// Sent after the config file was edited and the new values were accepted.
+pub struct ConfigChanged;
+
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    timer: Timer,
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn reload_config(
+    time: Res<Time>,
+    mut watcher: ResMut<ConfigWatcher>,
+    mut config: ResMut<GameConfig>,
+    mut config_changed: ResMut<Events<ConfigChanged>>,
+) {
+    if !watcher.timer.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+    let modified = modified_time(&watcher.path);
+    if modified.is_none() || modified == watcher.modified {
+        return;
+    }
+    watcher.modified = modified;
+
+    match GameConfig::load(&watcher.path) {
+        Ok(new_config) => {
+            if new_config != *config {
+                println!("Reloaded config from {}", watcher.path.display());
+                *config = new_config;
+                config_changed.send(ConfigChanged);
+            }
+        }
+        Err(e) => eprintln!(
+            "Keeping previous config, {} is rejected: {}",
+            watcher.path.display(),
+            e
+        ),
+    }
+}
+
+pub struct ConfigPlugin;
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let path = PathBuf::from(CONFIG_PATH);
+        let config = if path.exists() {
+            match GameConfig::load(&path) {
+                Ok(config) => config,
+                Err(e) => panic!("Failed to load {}: {}", path.display(), e),
+            }
+        } else {
+            println!("No config at {}, using defaults", path.display());
+            GameConfig::default()
+        };
+
+        app.add_resource(config)
+            .add_resource(ConfigWatcher {
+                modified: modified_time(&path),
+                path,
+                timer: Timer::from_seconds(RELOAD_CHECK_INTERVAL, true),
+            })
+            .add_event::<ConfigChanged>()
+            .add_system(reload_config.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(source: &str) -> Vec<String> {
+        match GameConfig::from_ron(source) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            other => panic!("expected invalid values, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(GameConfig::default().validate().is_ok());
+        assert_eq!(GameConfig::from_ron("()").unwrap(), GameConfig::default());
+    }
+
+    #[test]
+    fn missing_fields_keep_defaults() {
+        let config = GameConfig::from_ron("(person_speed: 50.0)").unwrap();
+        assert_eq!(config.person_speed, 50.);
+        assert_eq!(
+            config.house_wood_cost,
+            GameConfig::default().house_wood_cost
+        );
+    }
+
+    #[test]
+    fn rejects_empty_range() {
+        let problems = problems("(resource_clusters: (4, 4), starting_houses: (5, 2))");
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("resource_clusters"));
+        assert!(problems[1].starts_with("starting_houses"));
+    }
+
+    #[test]
+    fn rejects_zero_cost() {
+        assert_eq!(
+            problems("(house_wood_cost: 0)"),
+            vec!["house_wood_cost must be at least 1".to_string()]
+        );
+    }
+
+    #[test]
+    fn rejects_non_positive_numbers() {
+        let problems = problems("(person_speed: 0.0, day_length: -1.0)");
+        assert_eq!(problems.len(), 2);
+    }
+
+    #[test]
+    fn rejects_rarity_out_of_range() {
+        assert_eq!(problems("(resource_rarity: 1e-40)").len(), 1);
+        assert_eq!(problems("(resource_rarity: 100.0)").len(), 1);
+        assert!(GameConfig::from_ron("(resource_rarity: 0.5)").is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_field() {
+        assert!(matches!(
+            GameConfig::from_ron("(no_such_field: 1)"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,251 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    boat::Dock,
+    config::GameConfig,
+    house::House,
+    island::{Island, IslandNR},
+    names::NameGenerator,
+    person::Person,
+    resource::NaturalResourceType,
+    ui::{text_bundle, UiFont},
+    weather::Windbreak,
+    workshop::Workshop,
+    GameStates, Materials, MyStages,
+};
+
+pub const FACTION_COLORS: [Color; 6] = [
+    Color::rgb(0.2, 0.4, 1.),
+    Color::rgb(1., 0.3, 0.2),
+    Color::rgb(0.2, 0.8, 0.3),
+    Color::rgb(0.9, 0.7, 0.1),
+    Color::rgb(0.7, 0.3, 0.9),
+    Color::rgb(0.1, 0.8, 0.8),
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FactionId(pub usize);
+
+/// Faction an island, house or person belongs to. Houses and people inherit it from their island.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Owner(pub Option<FactionId>);
+
+#[derive(Debug)]
+pub struct Faction {
+    pub id: FactionId,
+    pub name: String,
+    pub color: Color,
+    pub house_material: Handle<ColorMaterial>,
+    pub home_island: Option<Entity>,
+    /// Sum of the stock of every island the faction owns.
+    pub stock: HashMap<NaturalResourceType, u32>,
+    pub islands: usize,
+    pub houses: usize,
+    pub population: usize,
+    pub score: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct Factions {
+    pub list: Vec<Faction>,
+    /// The faction the local player controls.
+    pub player: FactionId,
+}
+
+impl Factions {
+    pub fn get(&self, id: FactionId) -> Option<&Faction> {
+        self.list.get(id.0)
+    }
+
+    pub fn name_of(&self, owner: Owner) -> String {
+        owner
+            .0
+            .and_then(|id| self.get(id))
+            .map_or_else(|| "Unclaimed".to_string(), |faction| faction.name.clone())
+    }
+}
+
+pub struct FactionScoreText(pub FactionId);
+
+/// Creates the factions and gives each a home island, lowest entity id first.
+fn setup_factions(
+    commands: &mut Commands,
+    config: Res<GameConfig>,
+    mut names: ResMut<NameGenerator>,
+    mut factions: ResMut<Factions>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    islands_query: Query<Entity, With<Island>>,
+) {
+    let mut islands: Vec<Entity> = islands_query.iter().collect();
+    islands.sort_by_key(|island| island.id());
+
+    for i in 0..config.factions {
+        let id = FactionId(i);
+        let color = FACTION_COLORS[i % FACTION_COLORS.len()];
+        let home_island = islands.get(i).copied();
+        match home_island {
+            Some(island) => commands.insert_one(island, Owner(Some(id))),
+            None => println!("No island left for faction {}, it starts without one", i),
+        };
+        let name = format!("House {}", names.family_name());
+        println!("Faction {} starts on {:?}", name, home_island);
+        factions.list.push(Faction {
+            id,
+            name,
+            color,
+            house_material: color_materials.add(color.into()),
+            home_island,
+            stock: HashMap::default(),
+            islands: 0,
+            houses: 0,
+            population: 0,
+            score: 0,
+        });
+    }
+}
+
+fn setup_faction_scores(commands: &mut Commands, font: Res<UiFont>, factions: Res<Factions>) {
+    for (i, faction) in factions.list.iter().enumerate() {
+        let mut text = text_bundle(&font, "", 14.);
+        text.text.style.color = faction.color;
+        text.style.position_type = PositionType::Absolute;
+        text.style.position = Rect {
+            left: Val::Px(300.),
+            top: Val::Px(34. + i as f32 * 18.),
+            ..Default::default()
+        };
+        commands.spawn(text).with(FactionScoreText(faction.id));
+    }
+}
+
+/// Hands the owner of each island down to its buildings and the residents of its houses, and
+/// tints the houses.
+pub fn inherit_owners(
+    commands: &mut Commands,
+    materials: Res<Materials>,
+    factions: Res<Factions>,
+    islands_query: Query<&Owner, With<Island>>,
+    houses_query: Query<(Entity, &House, Option<&Owner>)>,
+    docks_query: Query<(Entity, &Dock, Option<&Owner>)>,
+    workshops_query: Query<(Entity, &Workshop, Option<&Owner>)>,
+    windbreaks_query: Query<(Entity, &Windbreak, Option<&Owner>)>,
+    people_query: Query<(Entity, &Person, Option<&Owner>)>,
+) {
+    let island_owner = |island: Entity| islands_query.get(island).ok().copied().unwrap_or_default();
+
+    for (entity, house, owner) in houses_query.iter() {
+        let wanted = island_owner(house.island);
+        if owner != Some(&wanted) {
+            let material = wanted.0.and_then(|id| factions.get(id)).map_or_else(
+                || materials.house_material.clone(),
+                |faction| faction.house_material.clone(),
+            );
+            commands.insert(entity, (wanted, material));
+        }
+    }
+    let buildings = docks_query
+        .iter()
+        .map(|(entity, dock, owner)| (entity, dock.island, owner))
+        .chain(
+            workshops_query
+                .iter()
+                .map(|(entity, workshop, owner)| (entity, workshop.island, owner)),
+        )
+        .chain(
+            windbreaks_query
+                .iter()
+                .map(|(entity, windbreak, owner)| (entity, windbreak.island, owner)),
+        );
+    for (entity, island, owner) in buildings {
+        let wanted = island_owner(island);
+        if owner != Some(&wanted) {
+            commands.insert_one(entity, wanted);
+        }
+    }
+    for (entity, person, owner) in people_query.iter() {
+        let wanted = match person.house.and_then(|house| houses_query.get(house).ok()) {
+            Some((_, house, _)) => island_owner(house.island),
+            // the homeless stay with their faction until they are rehoused
+            None if owner.is_some() => continue,
+            None => Owner::default(),
+        };
+        if owner != Some(&wanted) {
+            commands.insert_one(entity, wanted);
+        }
+    }
+}
+
+/// Recomputes every faction's stock, counts and score from what it owns.
+fn update_faction_stats(
+    mut factions: ResMut<Factions>,
+    islands_query: Query<(&Owner, &IslandNR), With<Island>>,
+    houses_query: Query<&Owner, With<House>>,
+    people_query: Query<&Owner, With<Person>>,
+) {
+    for faction in factions.list.iter_mut() {
+        faction.stock.clear();
+        faction.islands = 0;
+        faction.houses = 0;
+        faction.population = 0;
+    }
+    let faction_of = |owner: &Owner| owner.0.map(|id| id.0);
+    for (owner, island_nr) in islands_query.iter() {
+        if let Some(faction) = faction_of(owner).and_then(|i| factions.list.get_mut(i)) {
+            faction.islands += 1;
+            for (nr_type, count) in island_nr.0.iter() {
+                *faction.stock.entry(*nr_type).or_insert(0) += count;
+            }
+        }
+    }
+    for owner in houses_query.iter() {
+        if let Some(faction) = faction_of(owner).and_then(|i| factions.list.get_mut(i)) {
+            faction.houses += 1;
+        }
+    }
+    for owner in people_query.iter() {
+        if let Some(faction) = faction_of(owner).and_then(|i| factions.list.get_mut(i)) {
+            faction.population += 1;
+        }
+    }
+    for faction in factions.list.iter_mut() {
+        let stock: u32 = faction.stock.values().sum();
+        faction.score = stock + 10 * faction.houses as u32 + 5 * faction.population as u32;
+    }
+}
+
+fn update_faction_scores(
+    factions: Res<Factions>,
+    mut text_query: Query<(&mut Text, &FactionScoreText)>,
+) {
+    for (mut text, score_text) in text_query.iter_mut() {
+        let faction = match factions.get(score_text.0) {
+            Some(faction) => faction,
+            None => continue,
+        };
+        let you = if faction.id == factions.player {
+            " (you)"
+        } else {
+            ""
+        };
+        let value = format!(
+            "{}{}: score {}, {} islands, {} people",
+            faction.name, you, faction.score, faction.islands, faction.population
+        );
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub struct FactionsPlugin;
+impl Plugin for FactionsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Factions>()
+            // home islands are handed out before the starting houses are built on them
+            .add_world_gen_system(MyStages::Factions, setup_factions.system())
+            .add_world_gen_system(MyStages::Ui, setup_faction_scores.system())
+            .add_game_system(inherit_owners.system())
+            .add_game_system(update_faction_stats.system())
+            .add_game_system(update_faction_scores.system());
+    }
+}
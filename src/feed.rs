@@ -0,0 +1,339 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{
+    building::BuildingType,
+    follow::CameraFollow,
+    house::HouseTier,
+    island::{Island, Title},
+    resource::NaturalResourceType,
+    ui::{text_bundle, UiFont},
+    weather::WeatherKind,
+    GameStates, GameTime, MainCamera, MyStages,
+};
+
+const FEED_CAPACITY: usize = 50;
+const FEED_ROWS: usize = 8;
+
+#[derive(Debug, Clone)]
+pub enum GameEvent {
+    HouseBuilt {
+        house: Entity,
+        island: Entity,
+        position: Vec2,
+    },
+    HouseUpgraded {
+        house: Entity,
+        island: Entity,
+        tier: HouseTier,
+        position: Vec2,
+    },
+    BuildingDemolished {
+        building: BuildingType,
+        island: Entity,
+        position: Vec2,
+    },
+    /// A storm brought a house down.
+    HouseDestroyed {
+        island: Entity,
+        position: Vec2,
+    },
+    WeatherForecast {
+        weather: WeatherKind,
+        seconds: u32,
+    },
+    WeatherChanged {
+        weather: WeatherKind,
+    },
+    PersonSpawned {
+        person: Entity,
+        name: String,
+        island: Option<Entity>,
+        position: Vec2,
+    },
+    ResourceGathered {
+        person: Entity,
+        name: String,
+        resource: NaturalResourceType,
+        island: Option<Entity>,
+        position: Vec2,
+    },
+    IslandSettled {
+        island: Entity,
+        faction: String,
+        position: Vec2,
+    },
+    /// A resident gave up on an island that ran dry in a drought.
+    PersonLeft {
+        name: String,
+        island: Entity,
+        position: Vec2,
+    },
+}
+
+impl GameEvent {
+    pub fn island(&self) -> Option<Entity> {
+        match self {
+            GameEvent::HouseBuilt { island, .. } => Some(*island),
+            GameEvent::HouseUpgraded { island, .. } => Some(*island),
+            GameEvent::BuildingDemolished { island, .. } => Some(*island),
+            GameEvent::HouseDestroyed { island, .. } => Some(*island),
+            GameEvent::WeatherForecast { .. } | GameEvent::WeatherChanged { .. } => None,
+            GameEvent::PersonSpawned { island, .. } => *island,
+            GameEvent::ResourceGathered { island, .. } => *island,
+            GameEvent::IslandSettled { island, .. } => Some(*island),
+            GameEvent::PersonLeft { island, .. } => Some(*island),
+        }
+    }
+
+    pub fn position(&self) -> Vec2 {
+        match self {
+            GameEvent::HouseBuilt { position, .. } => *position,
+            GameEvent::HouseUpgraded { position, .. } => *position,
+            GameEvent::BuildingDemolished { position, .. } => *position,
+            GameEvent::HouseDestroyed { position, .. } => *position,
+            // weather is everywhere, so jump to the middle of the map
+            GameEvent::WeatherForecast { .. } | GameEvent::WeatherChanged { .. } => Vec2::zero(),
+            GameEvent::PersonSpawned { position, .. } => *position,
+            GameEvent::ResourceGathered { position, .. } => *position,
+            GameEvent::IslandSettled { position, .. } => *position,
+            GameEvent::PersonLeft { position, .. } => *position,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            GameEvent::HouseBuilt { .. } => "A house was built".to_string(),
+            GameEvent::HouseUpgraded { tier, .. } => format!("A house became a {:?}", tier),
+            GameEvent::BuildingDemolished { building, .. } => {
+                format!("A {} was demolished", building.name())
+            }
+            GameEvent::HouseDestroyed { .. } => "A storm destroyed a house".to_string(),
+            GameEvent::WeatherForecast { weather, seconds } => {
+                format!("{:?} expected in {}s", weather, seconds)
+            }
+            GameEvent::WeatherChanged { weather } => format!("The weather turns to {:?}", weather),
+            GameEvent::PersonSpawned { name, .. } => format!("{} moved in", name),
+            GameEvent::ResourceGathered { name, resource, .. } => {
+                format!("{} gathered some {:?}", name, resource)
+            }
+            GameEvent::IslandSettled { faction, .. } => {
+                format!("{} founded a new settlement", faction)
+            }
+            GameEvent::PersonLeft { name, .. } => format!("{} left in the drought", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FeedEntry {
+    pub time: f64,
+    pub event: GameEvent,
+}
+
+/// Most recent game events, newest last, plus the island the feed is filtered to.
+#[derive(Debug, Default)]
+pub struct GameFeed {
+    pub entries: VecDeque<FeedEntry>,
+    pub filter: Option<Entity>,
+}
+
+impl GameFeed {
+    /// Entries passing the current filter, newest first.
+    pub fn visible(&self) -> impl Iterator<Item = &FeedEntry> {
+        let filter = self.filter;
+        self.entries
+            .iter()
+            .rev()
+            .filter(move |entry| filter.is_none() || entry.event.island() == filter)
+    }
+}
+
+pub struct FeedMaterials {
+    pub row: Handle<ColorMaterial>,
+    pub row_hovered: Handle<ColorMaterial>,
+}
+
+pub enum FeedText {
+    Header,
+    Row(usize),
+}
+
+pub struct FeedRow(pub usize);
+
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub fn setup_feed(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    materials: Res<crate::Materials>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let feed_materials = FeedMaterials {
+        row: color_materials.add(Color::NONE.into()),
+        row_hovered: color_materials.add(Color::rgba(1., 1., 1., 0.2).into()),
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(360.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.),
+                    top: Val::Px(10.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: Rect::all(Val::Px(4.)),
+                ..Default::default()
+            },
+            material: materials.transparent.clone(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn(text_bundle(&font, "", 16.))
+                .with(FeedText::Header);
+            for i in 0..FEED_ROWS {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(100.), Val::Px(20.)),
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        material: feed_materials.row.clone(),
+                        ..Default::default()
+                    })
+                    .with(FeedRow(i))
+                    .with_children(|row| {
+                        row.spawn(text_bundle(&font, "", 14.))
+                            .with(FeedText::Row(i));
+                    });
+            }
+        })
+        .insert_resource(feed_materials);
+}
+
+fn collect_game_events(
+    time: Res<GameTime>,
+    events: Res<Events<GameEvent>>,
+    mut event_reader: Local<EventReader<GameEvent>>,
+    mut feed: ResMut<GameFeed>,
+) {
+    for ev in event_reader.iter(&events) {
+        feed.entries.push_back(FeedEntry {
+            time: time.seconds_since_startup(),
+            event: ev.clone(),
+        });
+        if feed.entries.len() > FEED_CAPACITY {
+            feed.entries.pop_front();
+        }
+    }
+}
+
+fn cycle_feed_filter(
+    keys: Res<Input<KeyCode>>,
+    mut feed: ResMut<GameFeed>,
+    islands_query: Query<Entity, With<Island>>,
+) {
+    if !keys.just_pressed(KeyCode::F) {
+        return;
+    }
+    let mut islands: Vec<Entity> = islands_query.iter().collect();
+    islands.sort_by_key(|island| island.id());
+
+    feed.filter = match feed.filter {
+        None => islands.first().copied(),
+        Some(current) => islands
+            .iter()
+            .position(|island| *island == current)
+            .and_then(|i| islands.get(i + 1))
+            .copied(),
+    };
+}
+
+fn update_feed_ui(
+    feed: Res<GameFeed>,
+    titles_query: Query<&Title>,
+    mut text_query: Query<(&mut Text, &FeedText)>,
+) {
+    let island_name = |island: Option<Entity>| {
+        island
+            .and_then(|island| titles_query.get(island).ok())
+            .map(|title| title.0.clone())
+            .unwrap_or_else(|| "Nowhere".to_string())
+    };
+    let visible: Vec<&FeedEntry> = feed.visible().take(FEED_ROWS).collect();
+
+    for (mut text, feed_text) in text_query.iter_mut() {
+        let value = match feed_text {
+            FeedText::Header => match feed.filter {
+                None => "Events: all islands [F]".to_string(),
+                Some(_) => format!("Events: {} [F]", island_name(feed.filter)),
+            },
+            FeedText::Row(i) => match visible.get(*i) {
+                Some(entry) => format!(
+                    "{} [{}] {}",
+                    format_time(entry.time),
+                    island_name(entry.event.island()),
+                    entry.event.describe()
+                ),
+                None => String::new(),
+            },
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+fn handle_feed_clicks(
+    feed: Res<GameFeed>,
+    feed_materials: Res<FeedMaterials>,
+    mut interaction_query: Query<
+        (&Interaction, &FeedRow, &mut Handle<ColorMaterial>),
+        (Mutated<Interaction>, With<Button>),
+    >,
+    mut follow: ResMut<CameraFollow>,
+    mut camera_query: Query<&mut Transform, With<MainCamera>>,
+) {
+    for (interaction, row, mut material) in interaction_query.iter_mut() {
+        match *interaction {
+            Interaction::Clicked => {
+                if let Some(entry) = feed.visible().nth(row.0) {
+                    let position = entry.event.position();
+                    follow.stop();
+                    for mut cam in camera_query.iter_mut() {
+                        cam.translation.x = position.x;
+                        cam.translation.y = position.y;
+                    }
+                }
+            }
+            Interaction::Hovered => {
+                *material = feed_materials.row_hovered.clone();
+            }
+            Interaction::None => {
+                *material = feed_materials.row.clone();
+            }
+        }
+    }
+}
+
+pub struct FeedPlugin;
+impl Plugin for FeedPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<GameFeed>()
+            .add_event::<GameEvent>()
+            .add_world_gen_system(MyStages::Ui, setup_feed.system())
+            .add_game_system(collect_game_events.system())
+            .add_game_system(cycle_feed_filter.system())
+            .add_game_system(update_feed_ui.system())
+            .add_game_system(handle_feed_clicks.system());
+    }
+}
@@ -0,0 +1,408 @@
+use bevy::{
+    math::vec3,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    boat::{Boat, Dock},
+    faction::{FactionId, Factions, Owner},
+    island::{Island, IslandNR, Size as IslandSize, Title},
+    person::{Person, PersonTask},
+    resource::{NaturalResource, NaturalResourceType},
+    GameStates, GameTime, MyStages,
+};
+
+const CELL_SIZE: f32 = 50.;
+/// Half the side of the mapped square, in world units.
+const FOG_HALF_EXTENT: f32 = 1200.;
+const CELLS_PER_SIDE: usize = (2. * FOG_HALF_EXTENT / CELL_SIZE) as usize;
+const FOG_INTERVAL: f32 = 0.25;
+const PERSON_SIGHT: f32 = 80.;
+const BOAT_SIGHT: f32 = 200.;
+/// Docks look out over the sea, which is how neighbouring islands are first spotted.
+const DOCK_SIGHT: f32 = 450.;
+/// How far past their shores a faction watches the islands it owns.
+const ISLAND_SIGHT: f32 = 40.;
+/// Above resource nodes, the highest sprites in the world.
+const FOG_Z: f32 = 150.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sight {
+    Unexplored,
+    /// Seen before, but nobody is watching now.
+    Explored,
+    Watched,
+}
+
+/// What one faction has explored and is watching right now.
+#[derive(Debug)]
+pub struct VisibilityMap {
+    cells: Vec<Sight>,
+    islands: HashSet<Entity>,
+    /// Each discovered island's stock when it was last watched.
+    last_seen: HashMap<Entity, HashMap<NaturalResourceType, u32>>,
+}
+
+impl Default for VisibilityMap {
+    fn default() -> Self {
+        Self {
+            cells: vec![Sight::Unexplored; CELLS_PER_SIDE * CELLS_PER_SIDE],
+            islands: HashSet::default(),
+            last_seen: HashMap::default(),
+        }
+    }
+}
+
+fn cell_index(position: Vec2) -> Option<usize> {
+    let x = ((position.x + FOG_HALF_EXTENT) / CELL_SIZE).floor();
+    let y = ((position.y + FOG_HALF_EXTENT) / CELL_SIZE).floor();
+    let side = CELLS_PER_SIDE as f32;
+    if x < 0. || y < 0. || x >= side || y >= side {
+        return None;
+    }
+    Some(y as usize * CELLS_PER_SIDE + x as usize)
+}
+
+fn cell_center(i: usize) -> Vec2 {
+    Vec2::new(
+        (i % CELLS_PER_SIDE) as f32 * CELL_SIZE - FOG_HALF_EXTENT + CELL_SIZE / 2.,
+        (i / CELLS_PER_SIDE) as f32 * CELL_SIZE - FOG_HALF_EXTENT + CELL_SIZE / 2.,
+    )
+}
+
+impl VisibilityMap {
+    pub fn sight_at(&self, position: Vec2) -> Sight {
+        cell_index(position).map_or(Sight::Unexplored, |i| self.cells[i])
+    }
+
+    pub fn knows_island(&self, island: Entity) -> bool {
+        self.islands.contains(&island)
+    }
+
+    pub fn last_seen(&self, island: Entity) -> Option<&HashMap<NaturalResourceType, u32>> {
+        self.last_seen.get(&island)
+    }
+
+    /// Marks every cell whose centre is in range as watched.
+    fn watch(&mut self, center: Vec2, radius: f32) {
+        let side = CELLS_PER_SIDE as i64;
+        let reach = (radius / CELL_SIZE).ceil() as i64 + 1;
+        let cx = ((center.x + FOG_HALF_EXTENT) / CELL_SIZE).floor() as i64;
+        let cy = ((center.y + FOG_HALF_EXTENT) / CELL_SIZE).floor() as i64;
+        for y in (cy - reach).max(0)..=(cy + reach).min(side - 1) {
+            for x in (cx - reach).max(0)..=(cx + reach).min(side - 1) {
+                let i = (y * side + x) as usize;
+                if (cell_center(i) - center).length() <= radius {
+                    self.cells[i] = Sight::Watched;
+                }
+            }
+        }
+    }
+}
+
+/// Every faction's visibility map, indexed like `Factions::list`.
+#[derive(Debug, Default)]
+pub struct FogOfWar {
+    pub maps: Vec<VisibilityMap>,
+}
+
+impl FogOfWar {
+    pub fn map(&self, faction: FactionId) -> Option<&VisibilityMap> {
+        self.maps.get(faction.0)
+    }
+
+    pub fn sight(&self, faction: FactionId, position: Vec2) -> Sight {
+        self.map(faction)
+            .map_or(Sight::Unexplored, |map| map.sight_at(position))
+    }
+
+    pub fn knows_island(&self, faction: FactionId, island: Entity) -> bool {
+        self.map(faction)
+            .map_or(false, |map| map.knows_island(island))
+    }
+}
+
+pub struct FogTimer(Timer);
+
+pub struct FogCell(pub usize);
+
+/// Set on resource nodes once the player has watched them, so nodes that grew back unseen stay
+/// hidden until someone looks.
+pub struct Seen;
+
+pub struct FogMaterials {
+    unexplored: Handle<ColorMaterial>,
+    explored: Handle<ColorMaterial>,
+}
+
+fn setup_fog(commands: &mut Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    let fog_materials = FogMaterials {
+        unexplored: materials.add(Color::rgb(0.05, 0.05, 0.1).into()),
+        explored: materials.add(Color::rgba(0.05, 0.05, 0.1, 0.55).into()),
+    };
+    for i in 0..CELLS_PER_SIDE * CELLS_PER_SIDE {
+        let center = cell_center(i);
+        commands
+            .spawn(SpriteBundle {
+                material: fog_materials.unexplored.clone(),
+                sprite: Sprite::new(Vec2::splat(CELL_SIZE)),
+                transform: Transform::from_translation(vec3(center.x, center.y, FOG_Z)),
+                ..Default::default()
+            })
+            .with(FogCell(i));
+    }
+    commands.insert_resource(fog_materials);
+}
+
+/// Works out what each faction's people, boats, docks and islands can see, and what they have
+/// discovered so far.
+fn update_fog(
+    time: Res<GameTime>,
+    factions: Res<Factions>,
+    mut timer: ResMut<FogTimer>,
+    mut fog: ResMut<FogOfWar>,
+    people_query: Query<(&GlobalTransform, &Person, &Owner)>,
+    boats_query: Query<(&GlobalTransform, &Owner), With<Boat>>,
+    docks_query: Query<(&GlobalTransform, &Owner), With<Dock>>,
+    islands_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &IslandSize,
+            &Owner,
+            &Title,
+            &IslandNR,
+        ),
+        With<Island>,
+    >,
+) {
+    // the first update runs straight away so the world never shows uncovered
+    let first = fog.maps.len() != factions.list.len();
+    if !timer.0.tick(time.delta_seconds()).just_finished() && !first {
+        return;
+    }
+    if first {
+        fog.maps = factions
+            .list
+            .iter()
+            .map(|_| VisibilityMap::default())
+            .collect();
+    }
+
+    let mut viewers: Vec<(FactionId, Vec2, f32)> = Vec::new();
+    for (transform, person, owner) in people_query.iter() {
+        // passengers look out from their boat
+        if matches!(person.task, PersonTask::Sailing(_)) {
+            continue;
+        }
+        if let Some(faction) = owner.0 {
+            viewers.push((faction, transform.translation.truncate(), PERSON_SIGHT));
+        }
+    }
+    for (transform, owner) in boats_query.iter() {
+        if let Some(faction) = owner.0 {
+            viewers.push((faction, transform.translation.truncate(), BOAT_SIGHT));
+        }
+    }
+    for (transform, owner) in docks_query.iter() {
+        if let Some(faction) = owner.0 {
+            viewers.push((faction, transform.translation.truncate(), DOCK_SIGHT));
+        }
+    }
+    for (_, transform, size, owner, _, _) in islands_query.iter() {
+        if let Some(faction) = owner.0 {
+            viewers.push((
+                faction,
+                transform.translation.truncate(),
+                size.width / 2. + ISLAND_SIGHT,
+            ));
+        }
+    }
+
+    let player = factions.player;
+    for (i, map) in fog.maps.iter_mut().enumerate() {
+        for cell in map.cells.iter_mut() {
+            if *cell == Sight::Watched {
+                *cell = Sight::Explored;
+            }
+        }
+        let own_viewers = viewers.iter().filter(|(faction, _, _)| faction.0 == i);
+        for (_, position, radius) in own_viewers.clone() {
+            map.watch(*position, *radius);
+        }
+        for (island, transform, size, _, title, stock) in islands_query.iter() {
+            let center = transform.translation.truncate();
+            let watched = own_viewers.clone().any(|(_, position, radius)| {
+                (*position - center).length() <= radius + size.width / 2.
+            });
+            if !watched {
+                continue;
+            }
+            if map.islands.insert(island) && i == player.0 && !first {
+                println!("Discovered {}", title.0);
+            }
+            map.last_seen.insert(island, stock.0.clone());
+        }
+    }
+}
+
+/// Covers what the player hasn't explored, and dims what nobody of theirs is watching.
+fn draw_fog(
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    fog_materials: Res<FogMaterials>,
+    mut cells_query: Query<(&FogCell, &mut Handle<ColorMaterial>, &mut Visible)>,
+) {
+    let map = match fog.map(factions.player) {
+        Some(map) => map,
+        None => return,
+    };
+    for (cell, mut material, mut visible) in cells_query.iter_mut() {
+        let sight = map.cells[cell.0];
+        let shown = sight != Sight::Watched;
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+        let wanted = match sight {
+            Sight::Explored => &fog_materials.explored,
+            _ => &fog_materials.unexplored,
+        };
+        if *material != *wanted {
+            *material = wanted.clone();
+        }
+    }
+}
+
+/// Shows resource nodes the player has seen, as they were when last watched.
+fn reveal_resources(
+    commands: &mut Commands,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    mut resources_query: Query<
+        (Entity, &GlobalTransform, &mut Visible, Option<&Seen>),
+        With<NaturalResource>,
+    >,
+) {
+    let map = match fog.map(factions.player) {
+        Some(map) => map,
+        None => return,
+    };
+    for (entity, transform, mut visible, seen) in resources_query.iter_mut() {
+        let shown = match map.sight_at(transform.translation.truncate()) {
+            Sight::Watched => {
+                if seen.is_none() {
+                    commands.insert_one(entity, Seen);
+                }
+                true
+            }
+            Sight::Explored => seen.is_some(),
+            Sight::Unexplored => false,
+        };
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+    }
+}
+
+/// Hides other factions' boats wherever the player isn't watching. People are shown and hidden
+/// by `schedule_people` alone, so nothing reads them half way through a frame.
+fn hide_strangers(
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    mut boats_query: Query<(&GlobalTransform, &Owner, &mut Visible), With<Boat>>,
+) {
+    let player = factions.player;
+    for (transform, owner, mut visible) in boats_query.iter_mut() {
+        let mine = owner.0 == Some(player);
+        let shown = mine || fog.sight(player, transform.translation.truncate()) == Sight::Watched;
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+    }
+}
+
+pub struct FogPlugin;
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<FogOfWar>()
+            .add_resource(FogTimer(Timer::from_seconds(FOG_INTERVAL, true)))
+            .add_world_gen_system(MyStages::Ui, setup_fog.system())
+            .add_game_system(update_fog.system())
+            .add_game_system(draw_fog.system())
+            .add_game_system(reveal_resources.system())
+            .add_system_to_stage("changes", hide_strangers.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::Stage;
+
+    use super::*;
+    use crate::faction::Faction;
+
+    fn spawn_island(world: &mut World, x: f32, owner: Owner) -> Entity {
+        world.spawn((
+            Island,
+            GlobalTransform::from_translation(vec3(x, 0., 0.)),
+            IslandSize {
+                width: 100.,
+                height: 100.,
+            },
+            owner,
+            Title(format!("Island at {}", x)),
+            IslandNR(Default::default()),
+        ))
+    }
+
+    #[test]
+    fn a_dock_reveals_the_neighbouring_island() {
+        let faction = FactionId(0);
+        let mut world = World::new();
+        let mut resources = Resources::default();
+        let home = spawn_island(&mut world, 0., Owner(Some(faction)));
+        // the next ring out, where islands sit 500 units from the middle
+        let neighbour = spawn_island(&mut world, 500., Owner(None));
+        resources.insert(GameTime::default());
+        resources.insert(FogTimer(Timer::from_seconds(FOG_INTERVAL, true)));
+        resources.insert(FogOfWar::default());
+        resources.insert(Factions {
+            list: vec![Faction {
+                id: faction,
+                name: "Test".to_string(),
+                color: Color::WHITE,
+                house_material: Default::default(),
+                home_island: Some(home),
+                stock: Default::default(),
+                islands: 1,
+                houses: 0,
+                population: 0,
+                score: 0,
+            }],
+            player: faction,
+        });
+        let mut stage = SystemStage::single(update_fog.system());
+
+        stage.run(&mut world, &mut resources);
+        {
+            let fog = resources.get::<FogOfWar>().unwrap();
+            assert!(fog.knows_island(faction, home));
+            assert!(!fog.knows_island(faction, neighbour));
+        }
+
+        world.spawn((
+            Dock { island: home },
+            GlobalTransform::from_translation(vec3(50., 0., 0.)),
+            Owner(Some(faction)),
+        ));
+        resources
+            .get_mut::<GameTime>()
+            .unwrap()
+            .advance(FOG_INTERVAL);
+        stage.run(&mut world, &mut resources);
+        let fog = resources.get::<FogOfWar>().unwrap();
+        assert!(fog.knows_island(faction, neighbour));
+        assert_eq!(fog.sight(faction, Vec2::new(450., 0.)), Sight::Watched);
+    }
+}
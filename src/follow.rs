@@ -0,0 +1,193 @@
+use bevy::prelude::*;
+
+use crate::{
+    boat::Boat,
+    faction::{Factions, Owner},
+    fog::{FogOfWar, Sight},
+    house::House,
+    island::{Island, Size as IslandSize},
+    panel::SelectedIsland,
+    person::{Person, PersonTask},
+    CursorPosition, GameStates, GameTime, MainCamera, Selected,
+};
+
+/// How quickly the camera catches up with what it follows, per second.
+const FOLLOW_DAMPING: f32 = 4.;
+/// Slack around small sprites when picking what to follow under the cursor.
+const PICK_RADIUS: f32 = 8.;
+
+/// What the camera keeps centred on. Moving the camera by hand ends it.
+#[derive(Debug, Default)]
+pub struct CameraFollow(pub Option<Entity>);
+
+impl CameraFollow {
+    /// Hands the camera back to the player.
+    pub fn stop(&mut self) {
+        if self.0.take().is_some() {
+            println!("Camera stopped following");
+        }
+    }
+}
+
+fn island_at(
+    position: Vec2,
+    islands_query: &Query<(Entity, &GlobalTransform, &IslandSize), With<Island>>,
+) -> Option<Entity> {
+    islands_query
+        .iter()
+        .find(|(_, transform, size)| {
+            (position - transform.translation.truncate()).length() <= size.width / 2.
+        })
+        .map(|(island, _, _)| island)
+}
+
+/// `C` follows the first selected person, or the person or boat under the cursor, and stops
+/// following if the camera already is. `Tab` follows the next person on the current island.
+fn follow_controls(
+    keys: Res<Input<KeyCode>>,
+    cursor: Res<CursorPosition>,
+    selected: Res<Selected>,
+    selected_island: Res<SelectedIsland>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    mut follow: ResMut<CameraFollow>,
+    people_query: Query<(Entity, &GlobalTransform, &Person, Option<&Owner>)>,
+    boats_query: Query<(Entity, &GlobalTransform, &Owner), With<Boat>>,
+    houses_query: Query<&House>,
+    islands_query: Query<(Entity, &GlobalTransform, &IslandSize), With<Island>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+) {
+    // other factions' people and boats can only be followed where the player is watching
+    let player = factions.player;
+    let seen = |transform: &GlobalTransform, owner: Option<&Owner>| {
+        owner.map_or(false, |owner| owner.0 == Some(player))
+            || fog.sight(player, transform.translation.truncate()) == Sight::Watched
+    };
+
+    if keys.just_pressed(KeyCode::C) {
+        if follow.0.is_some() {
+            follow.stop();
+            return;
+        }
+        let under_cursor = people_query
+            .iter()
+            .map(|(entity, transform, _, owner)| (entity, transform, owner))
+            .chain(
+                boats_query
+                    .iter()
+                    .map(|(entity, transform, owner)| (entity, transform, Some(owner))),
+            )
+            .find(|(_, transform, owner)| {
+                seen(*transform, *owner)
+                    && (transform.translation.truncate() - cursor.world).length() <= PICK_RADIUS
+            })
+            .map(|(entity, _, _)| entity);
+        follow.0 = selected.items.first().copied().or(under_cursor);
+        if let Some(target) = follow.0 {
+            match people_query.get(target) {
+                Ok((_, _, person, _)) => println!("Camera following {}", person.name),
+                Err(_) => println!("Camera following {:?}", target),
+            }
+        }
+    }
+
+    if !keys.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    // the island of whoever is followed, else the open panel's, else the one in view
+    let followed = follow.0.and_then(|target| people_query.get(target).ok());
+    let island = followed
+        .and_then(|(_, _, person, _)| person.house)
+        .and_then(|house| houses_query.get(house).ok())
+        .map(|house| house.island)
+        .or(selected_island.0)
+        .or_else(|| {
+            let center = camera_query.iter().next()?.translation.truncate();
+            island_at(center, &islands_query)
+        });
+    let island = match island {
+        Some(island) => island,
+        None => return,
+    };
+    let mut people: Vec<(Entity, &Person)> = people_query
+        .iter()
+        .filter(|(_, transform, person, owner)| {
+            !matches!(person.task, PersonTask::Sailing(_))
+                && seen(*transform, *owner)
+                && island_at(transform.translation.truncate(), &islands_query) == Some(island)
+        })
+        .map(|(entity, _, person, _)| (entity, person))
+        .collect();
+    people.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+    let next = match people
+        .iter()
+        .position(|(entity, _)| Some(*entity) == follow.0)
+    {
+        Some(i) => people.get((i + 1) % people.len()),
+        None => people.first(),
+    };
+    if let Some((entity, person)) = next {
+        println!("Camera following {}", person.name);
+        follow.0 = Some(*entity);
+    }
+}
+
+/// Eases the camera towards what it follows, or the boat a followed person is sailing on.
+/// Strangers are only followed while the player is watching them.
+fn follow_camera(
+    time: Res<GameTime>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    mut follow: ResMut<CameraFollow>,
+    targets_query: Query<(&GlobalTransform, Option<&Person>, Option<&Owner>)>,
+    mut camera_query: Query<&mut Transform, With<MainCamera>>,
+) {
+    let target = match follow.0 {
+        Some(target) => target,
+        None => return,
+    };
+    let found = match targets_query.get(target) {
+        Ok((
+            _,
+            Some(Person {
+                task: PersonTask::Sailing(boat),
+                ..
+            }),
+            _,
+        )) => targets_query
+            .get(*boat)
+            .ok()
+            .map(|(transform, _, owner)| (transform, owner)),
+        Ok((transform, _, owner)) => Some((transform, owner)),
+        Err(_) => None,
+    };
+    let player = factions.player;
+    let position = match found {
+        Some((transform, owner))
+            if owner.map_or(false, |owner| owner.0 == Some(player))
+                || fog.sight(player, transform.translation.truncate()) == Sight::Watched =>
+        {
+            transform.translation.truncate()
+        }
+        _ => {
+            // despawned, left a boat that is gone, or out of sight under the fog
+            follow.stop();
+            return;
+        }
+    };
+    let blend = 1. - (-FOLLOW_DAMPING * time.delta_seconds()).exp();
+    for mut camera in camera_query.iter_mut() {
+        let offset = position - camera.translation.truncate();
+        camera.translation.x += offset.x * blend;
+        camera.translation.y += offset.y * blend;
+    }
+}
+
+pub struct FollowPlugin;
+impl Plugin for FollowPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<CameraFollow>()
+            .add_game_system(follow_controls.system())
+            .add_game_system(follow_camera.system());
+    }
+}
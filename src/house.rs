@@ -1,47 +1,552 @@
 use crate::{
-    island::{Island, Size},
-    Materials,
+    building::{Building, BuildingType},
+    command::GameCommand,
+    config::GameConfig,
+    faction::{Factions, Owner},
+    feed::GameEvent,
+    island::{Island, IslandGoods, IslandNR, Size},
+    layout::find_free_spot,
+    names::NameGenerator,
+    person::{spawn_person, Person, PersonTask},
+    resource::NaturalResourceType,
+    workshop::{can_afford_wares, pay_wares, Good, Ware},
+    CursorPosition, GameStates, GameTime, Materials, WorldRng,
 };
-use bevy::{math::vec3, prelude::*};
-use rand::{self, Rng};
+use bevy::{math::vec3, prelude::*, utils::HashMap};
+use rand::Rng;
+
+/// Seconds between households with room growing by one, if their island has food.
+const GROWTH_INTERVAL: f32 = 15.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HouseTier {
+    Hut,
+    Cottage,
+    Manor,
+}
+
+impl HouseTier {
+    pub fn capacity(self) -> usize {
+        match self {
+            HouseTier::Hut => 2,
+            HouseTier::Cottage => 4,
+            HouseTier::Manor => 8,
+        }
+    }
+
+    pub fn next(self) -> Option<Self> {
+        match self {
+            HouseTier::Hut => Some(HouseTier::Cottage),
+            HouseTier::Cottage => Some(HouseTier::Manor),
+            HouseTier::Manor => None,
+        }
+    }
+
+    /// What it takes to upgrade a house to this tier.
+    pub fn cost(self) -> Vec<(Ware, u32)> {
+        match self {
+            HouseTier::Hut => vec![],
+            HouseTier::Cottage => vec![
+                (Ware::Raw(NaturalResourceType::Wood), 2),
+                (Ware::Good(Good::Planks), 4),
+            ],
+            HouseTier::Manor => vec![
+                (Ware::Good(Good::Planks), 8),
+                (Ware::Good(Good::Tools), 2),
+                (Ware::Raw(NaturalResourceType::Gold), 2),
+            ],
+        }
+    }
+
+    /// Everything spent upgrading a hut to this tier, one step at a time.
+    pub fn upgrades_cost(self) -> Vec<(Ware, u32)> {
+        let mut cost = Vec::new();
+        let mut tier = HouseTier::Hut;
+        while tier != self {
+            tier = match tier.next() {
+                Some(next) => next,
+                None => break,
+            };
+            cost.extend(tier.cost());
+        }
+        cost
+    }
+}
 
 pub struct House {
     pub island: Entity,
+    /// Family name shared by everyone living here.
+    pub family: String,
+    pub tier: HouseTier,
+    /// Everyone whose `Person::house` is this house, kept in sync by `sync_residents`.
+    pub residents: Vec<Entity>,
+}
+
+impl House {
+    pub fn capacity(&self) -> usize {
+        self.tier.capacity()
+    }
+
+    pub fn has_room(&self) -> bool {
+        self.residents.len() < self.capacity()
+    }
+}
+
+/// Sits on top of a house and shows its tier.
+pub struct Roof;
+
+pub struct GrowthTimer(Timer);
+
+pub fn spawn_house(
+    commands: &mut Commands,
+    materials: &Materials,
+    config: &GameConfig,
+    island: Entity,
+    family: String,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            material: materials.house_material.clone(),
+            sprite: Sprite::new(Vec2::new(config.house_size.0, config.house_size.1)),
+            transform: Transform::from_translation(vec3(position.x, position.y, 0.)),
+            ..Default::default()
+        })
+        .with(House {
+            island,
+            family,
+            tier: HouseTier::Hut,
+            residents: Vec::new(),
+        })
+        .with(Building(BuildingType::House))
+        .with_children(|parent| {
+            parent
+                .spawn(SpriteBundle {
+                    material: materials.roof_material.clone(),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: false,
+                    },
+                    ..Default::default()
+                })
+                .with(Roof);
+        })
+        .current_entity()
+        .unwrap()
 }
 
 pub fn build_house(
     commands: &mut Commands,
     materials: Res<Materials>,
-    query: Query<(Entity, &Size, &crate::island::Title, &Transform), With<Island>>,
+    config: Res<GameConfig>,
+    mut world_rng: ResMut<WorldRng>,
+    mut names: ResMut<NameGenerator>,
+    query: Query<(Entity, &Size, &crate::island::Title, &Transform, &Owner), With<Island>>,
 ) {
-    let mut rng = rand::thread_rng();
-    for (island_entity, island_size, island_title, island_transform) in query.iter() {
-        let number_of_houses: usize = rng.gen_range(1..10);
-        let mut houses: Vec<Entity> = Vec::with_capacity(number_of_houses);
+    let rng = &mut world_rng.0;
+    for (island_entity, island_size, island_title, island_transform, owner) in query.iter() {
+        // only home islands start settled, the rest wait for boats
+        if owner.0.is_none() {
+            continue;
+        }
+        let (min_houses, max_houses) = config.starting_houses;
+        let number_of_houses: usize = rng.gen_range(min_houses..max_houses);
+        let mut houses: Vec<(Vec2, Vec2)> = Vec::with_capacity(number_of_houses);
+        let center = island_transform.translation.truncate();
         let radius = island_size.width / 2.;
 
-        for i in 0..number_of_houses {
-            let angle = i as f32 * std::f32::consts::TAU / number_of_houses as f32;
-            let x = angle.cos() * radius + island_transform.translation.x;
-            let y = angle.sin() * radius + island_transform.translation.y;
+        for _ in 0..number_of_houses {
+            let spot = find_free_spot(
+                rng,
+                BuildingType::House,
+                &config,
+                island_entity,
+                center,
+                radius,
+                &houses,
+            );
+            let position = match spot {
+                Some(position) => position,
+                None => {
+                    println!(
+                        "Island {} is full after {} houses",
+                        island_title.0,
+                        houses.len()
+                    );
+                    break;
+                }
+            };
             println!(
                 "Building house on island {} with entity_id {:?}",
                 island_title.0, island_entity
             );
-            let house = commands
-                .spawn(SpriteBundle {
-                    material: materials.house_material.clone(),
-                    sprite: Sprite::new(Vec2::new(10.0, 10.0)),
-                    transform: Transform::from_translation(vec3(x, y, 0.)),
-                    ..Default::default()
-                })
-                .with(House {
-                    island: island_entity,
-                })
-                .current_entity()
-                .unwrap();
-            houses.push(house);
+            spawn_house(
+                commands,
+                &materials,
+                &config,
+                island_entity,
+                names.family_name(),
+                position,
+            );
+            houses.push((position, BuildingType::House.size(&config)));
+        }
+    }
+}
+
+/// `Person::house` is the source of truth, every house's resident list follows it.
+fn sync_residents(
+    people_query: Query<(Entity, &Person)>,
+    mut houses_query: Query<(Entity, &mut House)>,
+) {
+    let mut residents: HashMap<Entity, Vec<Entity>> = HashMap::default();
+    for (entity, person) in people_query.iter() {
+        if let Some(house) = person.house {
+            residents.entry(house).or_insert_with(Vec::new).push(entity);
+        }
+    }
+    for (entity, mut house) in houses_query.iter_mut() {
+        let mut wanted = residents.remove(&entity).unwrap_or_default();
+        wanted.sort();
+        if house.residents != wanted {
+            house.residents = wanted;
+        }
+    }
+}
+
+/// Moves the homeless, and anyone whose house is gone, into a house with room on the island
+/// they are standing on, if the island belongs to their faction.
+pub fn rehouse_homeless(
+    islands_query: Query<(Entity, &GlobalTransform, &Size, &Owner), With<Island>>,
+    houses_query: Query<(Entity, &House)>,
+    mut people_query: Query<(&mut Person, &GlobalTransform, Option<&Owner>)>,
+) {
+    let mut occupants: HashMap<Entity, usize> = HashMap::default();
+    for (person, _, _) in people_query.iter_mut() {
+        if let Some(house) = person.house {
+            *occupants.entry(house).or_insert(0) += 1;
+        }
+    }
+    for (mut person, transform, owner) in people_query.iter_mut() {
+        if matches!(person.task, PersonTask::Sailing(_)) {
+            continue;
+        }
+        if let Some(house) = person.house {
+            if houses_query.get(house).is_ok() {
+                continue;
+            }
+            println!("{}'s house is gone", person.name);
+            person.house = None;
+        }
+        let position = transform.translation.truncate();
+        let island = islands_query.iter().find(|(_, island_transform, size, _)| {
+            (position - island_transform.translation.truncate()).length() <= size.width / 2.
+        });
+        let (island, island_owner) = match island {
+            Some((island, _, _, island_owner)) => (island, *island_owner),
+            None => continue,
+        };
+        if owner.map_or(false, |owner| *owner != island_owner) {
+            continue;
+        }
+        let free = houses_query.iter().find(|(entity, house)| {
+            house.island == island && occupants.get(entity).copied().unwrap_or(0) < house.capacity()
+        });
+        if let Some((house, _)) = free {
+            println!("{} moved into a new home", person.name);
+            person.house = Some(house);
+            *occupants.entry(house).or_insert(0) += 1;
+        }
+    }
+}
+
+/// Houses with room get a new resident now and then, eating some of their island's food.
+fn grow_households(
+    commands: &mut Commands,
+    time: Res<GameTime>,
+    materials: Res<Materials>,
+    mut timer: ResMut<GrowthTimer>,
+    mut names: ResMut<NameGenerator>,
+    mut game_events: ResMut<Events<GameEvent>>,
+    houses_query: Query<(Entity, &GlobalTransform, &House)>,
+    mut goods_query: Query<&mut IslandGoods>,
+) {
+    if !timer.0.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+    for (house_entity, transform, house) in houses_query.iter() {
+        if !house.has_room() {
+            continue;
+        }
+        let mut goods = match goods_query.get_mut(house.island) {
+            Ok(goods) => goods,
+            Err(_) => continue,
+        };
+        match goods.0.get_mut(&Good::Food) {
+            Some(food) if *food > 0 => *food -= 1,
+            _ => continue,
+        }
+        let position = transform.translation.truncate();
+        let name = names.person_name(&house.family);
+        let person = spawn_person(
+            commands,
+            &materials,
+            name.clone(),
+            Some(house_entity),
+            position,
+        );
+        game_events.send(GameEvent::PersonSpawned {
+            person,
+            name,
+            island: Some(house.island),
+            position,
+        });
+    }
+}
+
+/// `U` upgrades the player's house under the cursor.
+fn house_controls(
+    keys: Res<Input<KeyCode>>,
+    cursor: Res<CursorPosition>,
+    factions: Res<Factions>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+    houses_query: Query<(Entity, &GlobalTransform, &Sprite), With<House>>,
+) {
+    if !keys.just_pressed(KeyCode::U) {
+        return;
+    }
+    let hovered = houses_query.iter().find(|(_, transform, sprite)| {
+        let offset = cursor.world - transform.translation.truncate();
+        offset.x.abs() <= sprite.size.x / 2. && offset.y.abs() <= sprite.size.y / 2.
+    });
+    if let Some((house, _, _)) = hovered {
+        game_commands.send(GameCommand::UpgradeHouse {
+            faction: factions.player,
+            house,
+        });
+    }
+}
+
+fn handle_upgrade_commands(
+    events: Res<Events<GameCommand>>,
+    mut event_reader: Local<EventReader<GameCommand>>,
+    mut game_events: ResMut<Events<GameEvent>>,
+    mut houses_query: Query<(&GlobalTransform, &mut House)>,
+    mut islands_query: Query<(&Owner, &mut IslandNR, &mut IslandGoods)>,
+) {
+    for ev in event_reader.iter(&events) {
+        let (faction, house_entity) = match ev {
+            GameCommand::UpgradeHouse { faction, house } => (*faction, *house),
+            _ => continue,
+        };
+        let (transform, mut house) = match houses_query.get_mut(house_entity) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        let (owner, mut stock, mut goods) = match islands_query.get_mut(house.island) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        if owner.0 != Some(faction) {
+            continue;
+        }
+        let tier = match house.tier.next() {
+            Some(tier) => tier,
+            None => continue,
+        };
+        let cost = tier.cost();
+        if !can_afford_wares(&stock, &goods, &cost) {
+            println!("The {} family can't afford a {:?}", house.family, tier);
+            continue;
+        }
+        pay_wares(&mut stock, &mut goods, &cost);
+        house.tier = tier;
+        game_events.send(GameEvent::HouseUpgraded {
+            house: house_entity,
+            island: house.island,
+            tier,
+            position: transform.translation.truncate(),
+        });
+    }
+}
+
+/// Huts are bare, cottages get a roof and manors a wider golden one.
+fn update_house_looks(
+    config: Res<GameConfig>,
+    materials: Res<Materials>,
+    houses_query: Query<(&House, &Children), Changed<House>>,
+    mut roofs_query: Query<
+        (
+            &mut Sprite,
+            &mut Transform,
+            &mut Visible,
+            &mut Handle<ColorMaterial>,
+        ),
+        With<Roof>,
+    >,
+) {
+    let (width, height) = config.house_size;
+    for (house, children) in houses_query.iter() {
+        for child in children.iter() {
+            let (mut sprite, mut transform, mut visible, mut material) =
+                match roofs_query.get_mut(*child) {
+                    Ok(found) => found,
+                    Err(_) => continue,
+                };
+            let (size, roof_material) = match house.tier {
+                HouseTier::Hut => {
+                    visible.is_visible = false;
+                    continue;
+                }
+                HouseTier::Cottage => (
+                    Vec2::new(width + 2., height * 0.4),
+                    materials.roof_material.clone(),
+                ),
+                HouseTier::Manor => (
+                    Vec2::new(width + 6., height * 0.6),
+                    materials.manor_roof_material.clone(),
+                ),
+            };
+            visible.is_visible = true;
+            sprite.size = size;
+            transform.translation = vec3(0., (height + size.y) / 2., 1.);
+            if *material != roof_material {
+                *material = roof_material;
+            }
+        }
+    }
+}
+
+pub struct HousePlugin;
+impl Plugin for HousePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(GrowthTimer(Timer::from_seconds(GROWTH_INTERVAL, true)))
+            .add_game_system(sync_residents.system())
+            .add_game_system(rehouse_homeless.system())
+            .add_game_system(grow_households.system())
+            .add_game_system(house_controls.system())
+            .add_game_system(handle_upgrade_commands.system())
+            .add_game_system(update_house_looks.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::Stage;
+
+    use super::*;
+    use crate::faction::FactionId;
+
+    const FACTION: Owner = Owner(Some(FactionId(0)));
+
+    fn spawn_island(world: &mut World) -> Entity {
+        world.spawn((
+            Island,
+            GlobalTransform::default(),
+            Size {
+                width: 100.,
+                height: 100.,
+            },
+            FACTION,
+        ))
+    }
+
+    fn spawn_hut(world: &mut World, island: Entity) -> Entity {
+        world.spawn((House {
+            island,
+            family: "Ashby".to_string(),
+            tier: HouseTier::Hut,
+            residents: Vec::new(),
+        },))
+    }
+
+    fn spawn_resident(world: &mut World, house: Option<Entity>) -> Entity {
+        world.spawn((
+            Person {
+                house,
+                ..Default::default()
+            },
+            GlobalTransform::default(),
+            FACTION,
+        ))
+    }
+
+    fn residents(world: &World, house: Entity) -> Vec<Entity> {
+        world.get::<House>(house).unwrap().residents.clone()
+    }
+
+    fn housing_stage() -> SystemStage {
+        SystemStage::serial()
+            .with_system(rehouse_homeless.system())
+            .with_system(sync_residents.system())
+    }
+
+    #[test]
+    fn residents_follow_the_people_who_live_there() {
+        let mut world = World::new();
+        let mut resources = Resources::default();
+        let island = spawn_island(&mut world);
+        let a = spawn_hut(&mut world, island);
+        let b = spawn_hut(&mut world, island);
+        let mut first_two = vec![
+            spawn_resident(&mut world, Some(a)),
+            spawn_resident(&mut world, Some(a)),
+        ];
+        first_two.sort();
+        let third = spawn_resident(&mut world, Some(b));
+        let mut stage = SystemStage::single(sync_residents.system());
+
+        stage.run(&mut world, &mut resources);
+        assert_eq!(residents(&world, a), first_two);
+        assert_eq!(residents(&world, b), vec![third]);
+
+        world.get_mut::<Person>(first_two[0]).unwrap().house = Some(b);
+        stage.run(&mut world, &mut resources);
+        let mut moved = vec![first_two[0], third];
+        moved.sort();
+        assert_eq!(residents(&world, a), vec![first_two[1]]);
+        assert_eq!(residents(&world, b), moved);
+    }
+
+    #[test]
+    fn the_homeless_only_get_free_beds() {
+        let mut world = World::new();
+        let mut resources = Resources::default();
+        let island = spawn_island(&mut world);
+        let hut = spawn_hut(&mut world, island);
+        let settled = spawn_resident(&mut world, Some(hut));
+        let homeless: Vec<Entity> = (0..3).map(|_| spawn_resident(&mut world, None)).collect();
+        let mut stage = housing_stage();
+
+        stage.run(&mut world, &mut resources);
+        stage.run(&mut world, &mut resources);
+        let in_hut = residents(&world, hut);
+        assert_eq!(in_hut.len(), HouseTier::Hut.capacity());
+        assert!(in_hut.contains(&settled));
+        let still_homeless = homeless
+            .iter()
+            .filter(|person| world.get::<Person>(**person).unwrap().house.is_none())
+            .count();
+        assert_eq!(still_homeless, 2);
+    }
+
+    #[test]
+    fn people_whose_house_is_gone_move_or_stay_homeless() {
+        let mut world = World::new();
+        let mut resources = Resources::default();
+        let island = spawn_island(&mut world);
+        let gone = spawn_hut(&mut world, island);
+        let people: Vec<Entity> = (0..3)
+            .map(|_| spawn_resident(&mut world, Some(gone)))
+            .collect();
+        let hut = spawn_hut(&mut world, island);
+        world.despawn(gone).unwrap();
+        let mut stage = housing_stage();
+
+        stage.run(&mut world, &mut resources);
+        for person in people.iter() {
+            let house = world.get::<Person>(*person).unwrap().house;
+            assert!(house == None || house == Some(hut), "{:?}", house);
         }
-        // commands.push_children(island_entity, houses.as_slice());
+        assert_eq!(residents(&world, hut).len(), HouseTier::Hut.capacity());
     }
 }
@@ -1,6 +1,18 @@
 use crate::{
-    draw_hovered_islands, house::House, person::*, resource::NaturalResourceType, Materials,
-    MyStages,
+    building::{Building, BuildingType},
+    config::GameConfig,
+    draw_hovered_islands,
+    faction::Owner,
+    feed::GameEvent,
+    house::{spawn_house, House},
+    layout::find_free_spot,
+    names::NameGenerator,
+    person::*,
+    playing,
+    resource::NaturalResourceType,
+    road::RoadNetwork,
+    workshop::Good,
+    GameStates, Materials, MyStages, WorldRng, APP_STATE_STAGE,
 };
 use bevy::{
     ecs::Stage,
@@ -11,10 +23,6 @@ use bevy::{
 use bevy_prototype_lyon::{entity::ShapeBundle, prelude::*};
 use rand::{prelude::SliceRandom, Rng};
 
-pub struct IslandsResources {
-    pub available_names: Vec<String>,
-}
-
 pub struct Title(pub String);
 
 #[derive(Debug)]
@@ -32,29 +40,67 @@ pub struct Grass;
 
 pub struct Island;
 
+/// Sets which resources grow on and around an island.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Biome {
+    Forest,
+    Rocky,
+    Lagoon,
+}
+
+impl Biome {
+    pub const ALL: [Self; 3] = [Self::Forest, Self::Rocky, Self::Lagoon];
+
+    /// Relative chance of each resource type for a grove or vein on this biome.
+    /// Gold only comes from rare veins, see `gold_vein_chance`.
+    pub fn resource_weights(self) -> [(NaturalResourceType, u32); 4] {
+        use NaturalResourceType::*;
+        match self {
+            Biome::Forest => [(Wood, 7), (Water, 2), (Coal, 1), (Iron, 1)],
+            Biome::Rocky => [(Wood, 1), (Water, 1), (Coal, 4), (Iron, 4)],
+            Biome::Lagoon => [(Wood, 3), (Water, 5), (Coal, 1), (Iron, 1)],
+        }
+    }
+
+    pub fn gold_vein_chance(self) -> f64 {
+        match self {
+            Biome::Forest => 0.1,
+            Biome::Rocky => 0.5,
+            Biome::Lagoon => 0.2,
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct IslandNR(pub HashMap<NaturalResourceType, u32>);
 
+/// Refined goods made by the island's workshops.
+#[derive(Debug, Default)]
+pub struct IslandGoods(pub HashMap<Good, u32>);
+
 #[derive(Bundle)]
 pub struct IslandBundle {
     pub title: Title,
     pub size: Size,
     pub _i: Island,
     pub natural_resources: IslandNR,
+    pub goods: IslandGoods,
+    pub roads: RoadNetwork,
+    pub biome: Biome,
+    pub owner: Owner,
     // soil: ShapeBundle,
     // grass: ShapeBundle,
 }
 
-pub fn spawn_island_at(
+pub fn spawn_island_at<R: Rng>(
     commands: &mut Commands,
     materials: &Res<Materials>,
-    island_mat: &Res<IslandsResources>,
+    names: &mut NameGenerator,
+    rng: &mut R,
     translation: Vec3,
 ) {
     println!("Spawning island");
 
-    let mut rng = rand::thread_rng();
-
     let width: f32 = (translation.x.cos().abs() * 150.) + 50.;
     let triangle = shapes::Circle {
         radius: width / 2.,
@@ -66,11 +112,8 @@ pub fn spawn_island_at(
         height: width,
         origin: shapes::RectangleOrigin::Center,
     };
-    let new_name = island_mat
-        .available_names
-        .choose(&mut rng)
-        .unwrap()
-        .to_string();
+    let new_name = names.island_name();
+    let biome = *Biome::ALL.choose(rng).unwrap();
 
     commands
         .spawn(IslandBundle {
@@ -81,6 +124,10 @@ pub fn spawn_island_at(
             },
             _i: Island,
             natural_resources: Default::default(),
+            goods: Default::default(),
+            roads: Default::default(),
+            biome,
+            owner: Owner::default(),
         })
         .with(Transform::from_translation(translation))
         .with(GlobalTransform::default())
@@ -105,40 +152,65 @@ pub fn spawn_island_at(
         });
 }
 
+/// Spawns `config.islands` islands: one in the middle, the rest on rings around it, with
+/// ring `k` 500 units out and holding up to `6 * k` islands.
 pub fn spawn_islands(
     commands: &mut Commands,
     materials: Res<Materials>,
-    island_mat: Res<IslandsResources>,
+    config: Res<GameConfig>,
+    mut names: ResMut<NameGenerator>,
+    mut world_rng: ResMut<WorldRng>,
 ) {
-    spawn_island_at(commands, &materials, &island_mat, vec3(0., 0., 0.));
+    let rng = &mut world_rng.0;
+    spawn_island_at(commands, &materials, &mut names, rng, vec3(0., 0., 0.));
 
-    for i in 0..1 {
-        let x: f32 = 500. * (i as f32).cos();
-        let y: f32 = 500. * (i as f32).sin();
-        spawn_island_at(commands, &materials, &island_mat, vec3(x, y, 0.));
+    let mut remaining = config.islands.saturating_sub(1);
+    let mut ring = 1;
+    while remaining > 0 {
+        let on_ring = remaining.min(6 * ring);
+        for i in 0..on_ring {
+            let angle = i as f32 * std::f32::consts::TAU / on_ring as f32;
+            let x: f32 = 500. * ring as f32 * angle.cos();
+            let y: f32 = 500. * ring as f32 * angle.sin();
+            spawn_island_at(commands, &materials, &mut names, rng, vec3(x, y, 0.));
+        }
+        remaining -= on_ring;
+        ring += 1;
     }
 }
 
-fn handle_gather_events(
+pub fn handle_gather_events(
     events: Res<Events<GatherEvent>>,
     mut event_reader: Local<EventReader<GatherEvent>>,
+    mut game_events: ResMut<Events<GameEvent>>,
     houses_query: Query<&House>,
-    people_query: Query<&Person>,
+    people_query: Query<(&Person, &Transform)>,
     mut islands_query: Query<&mut IslandNR>,
 ) {
     for ev in event_reader.iter(&events) {
         let maybe_person = people_query.get(ev.0);
-        if let Ok(person) = maybe_person {
-            let maybe_house = person.house;
+        if let Ok((person, person_transform)) = maybe_person {
+            // the homeless, or people whose house or island is gone, gather for nobody
+            let mut island = None;
+            let maybe_house = person.house.and_then(|house| houses_query.get(house).ok());
             if let Some(house) = maybe_house {
-                let island_entity = houses_query.get(house).unwrap().island;
-                // let isl /z= islands_query.get(h)
-                let mut nr_isl = islands_query.get_mut(island_entity).unwrap();
-                // nr_isl.entry(ev.1).or_insert(default);
-                let count = nr_isl.0.entry(ev.1).or_insert(0);
-                *count += 1;
-                println!("Inlands resources {:?}", &nr_isl.0);
+                if let Ok(mut nr_isl) = islands_query.get_mut(house.island) {
+                    let count = nr_isl.0.entry(ev.1).or_insert(0);
+                    *count += 1;
+                    println!("Inlands resources {:?}", &nr_isl.0);
+                    island = Some(house.island);
+                }
             }
+            game_events.send(GameEvent::ResourceGathered {
+                person: ev.0,
+                name: person.name.clone(),
+                resource: ev.1,
+                island,
+                position: vec2(
+                    person_transform.translation.x,
+                    person_transform.translation.y,
+                ),
+            });
         }
     }
 }
@@ -146,41 +218,68 @@ fn handle_gather_events(
 fn handle_resources_changes(
     commands: &mut Commands,
     materials: Res<Materials>,
-    mut query: Query<(&mut IslandNR, &Transform, &Size, Entity), Mutated<IslandNR>>,
+    config: Res<GameConfig>,
+    mut names: ResMut<NameGenerator>,
+    mut world_rng: ResMut<WorldRng>,
+    mut game_events: ResMut<Events<GameEvent>>,
+    buildings_query: Query<(&GlobalTransform, &Sprite), With<Building>>,
+    mut query: Query<(&mut IslandNR, &Transform, &Size, &Title, Entity), Mutated<IslandNR>>,
 ) {
-    let mut rng = rand::thread_rng();
-    for (mut res, tr, size, en) in query.iter_mut() {
-        println!("Building a new house");
+    if !config.auto_build_houses {
+        return;
+    }
+    let rng = &mut world_rng.0;
+    let mut buildings: Vec<(Vec2, Vec2)> = buildings_query
+        .iter()
+        .map(|(transform, sprite)| (transform.translation.truncate(), sprite.size))
+        .collect();
+    for (mut res, tr, size, title, en) in query.iter_mut() {
         if let Some(mut wood) = res.0.get_mut(&NaturalResourceType::Wood) {
-            while *wood >= 2 {
-                let x = tr.translation.x + rng.gen_range(-size.width / 2.0..size.width / 2.0);
-                let y = tr.translation.y + rng.gen_range(-size.height / 2.0..size.height / 2.0);
-                let house = commands
-                    .spawn(SpriteBundle {
-                        material: materials.house_material.clone(),
-                        sprite: Sprite::new(Vec2::new(10.0, 10.0)),
-                        transform: Transform::from_translation(vec3(x, y, 0.)),
-                        ..Default::default()
-                    })
-                    .with(House { island: en })
-                    .current_entity()
-                    .unwrap();
-
-                commands
-                    .spawn(SpriteBundle {
-                        material: materials.skin.clone(),
-                        sprite: Sprite::new(Vec2::new(5.0, 11.0)),
-                        transform: Transform::from_translation(vec3(x, y, 10.)),
-                        ..Default::default()
-                    })
-                    .with(Person {
-                        name: AVAILABLE_PERSON_NAMES.choose(&mut rng).unwrap().to_string(),
-                        house: Some(house),
-                        ..Default::default()
-                    })
-                    .with(TargetPosition(None));
-
-                *wood -= 2;
+            while *wood >= config.house_wood_cost {
+                let spot = find_free_spot(
+                    rng,
+                    BuildingType::House,
+                    &config,
+                    en,
+                    tr.translation.truncate(),
+                    size.width / 2.,
+                    &buildings,
+                );
+                let (x, y) = match spot {
+                    Some(position) => (position.x, position.y),
+                    None => {
+                        println!("No room left on {} for another house", title.0);
+                        break;
+                    }
+                };
+                println!("Building a new house");
+                buildings.push((vec2(x, y), BuildingType::House.size(&config)));
+                let family = names.family_name();
+                let house = spawn_house(
+                    commands,
+                    &materials,
+                    &config,
+                    en,
+                    family.clone(),
+                    vec2(x, y),
+                );
+                game_events.send(GameEvent::HouseBuilt {
+                    house,
+                    island: en,
+                    position: vec2(x, y),
+                });
+
+                let name = names.person_name(&family);
+                let person =
+                    spawn_person(commands, &materials, name.clone(), Some(house), vec2(x, y));
+                game_events.send(GameEvent::PersonSpawned {
+                    person,
+                    name,
+                    island: Some(en),
+                    position: vec2(x, y),
+                });
+
+                *wood -= config.house_wood_cost;
             }
         }
     }
@@ -189,13 +288,14 @@ fn handle_resources_changes(
 pub struct IslandsPlugin;
 impl Plugin for IslandsPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_resource(IslandsResources {
-            available_names: vec!["Land #1".to_string(), "Ocor".to_string()],
-        })
-        .add_startup_system_to_stage(MyStages::Islands.to_str(), spawn_islands.system())
-        .add_system(draw_hovered_islands.system())
-        .add_stage_after(stage::UPDATE, "changes", SystemStage::parallel())
-        .add_system_to_stage("changes", handle_resources_changes.system())
-        .add_system(handle_gather_events.system());
+        app.add_world_gen_system(MyStages::Islands, spawn_islands.system())
+            .add_game_system(draw_hovered_islands.system())
+            .add_stage_after(
+                APP_STATE_STAGE,
+                "changes",
+                SystemStage::parallel().with_run_criteria(playing.system()),
+            )
+            .add_system_to_stage("changes", handle_resources_changes.system())
+            .add_game_system(handle_gather_events.system());
     }
 }
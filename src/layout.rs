@@ -0,0 +1,165 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    building::{check_placement, BuildingType, IslandSite, Placement},
+    config::GameConfig,
+};
+
+/// Gap kept clear between any two buildings.
+pub const BUILDING_SPACING: f32 = 4.;
+
+/// Finds a spot on the island where `building` fits on land and keeps its distance from
+/// `buildings` (positions and sizes), trying rings from the middle out to the shore. Returns
+/// `None` when the island is full.
+pub fn find_free_spot<R: Rng>(
+    rng: &mut R,
+    building: BuildingType,
+    config: &GameConfig,
+    island: Entity,
+    center: Vec2,
+    radius: f32,
+    buildings: &[(Vec2, Vec2)],
+) -> Option<Vec2> {
+    let size = building.size(config);
+    let step = size.x.max(size.y) + BUILDING_SPACING;
+    let shore = radius - size.length() / 2.;
+    if shore < 0. {
+        return None;
+    }
+    let site = IslandSite {
+        island,
+        center,
+        radius,
+        owned: true,
+        can_afford: true,
+    };
+
+    // the outermost ring hugs the shore, so coastal buildings have somewhere to go
+    let mut distances: Vec<f32> = (0..)
+        .map(|ring| ring as f32 * step)
+        .take_while(|distance| *distance < shore)
+        .collect();
+    distances.push(shore);
+
+    for distance in distances {
+        let count = ((TAU * distance / step).floor() as usize).max(1);
+        let phase = rng.gen_range(0.0..TAU);
+        for i in 0..count {
+            let angle = phase + i as f32 * TAU / count as f32;
+            let position = center + Vec2::new(angle.cos(), angle.sin()) * distance;
+            let placement = check_placement(
+                building,
+                position,
+                config,
+                std::iter::once(site),
+                buildings.iter().copied(),
+            );
+            if let Placement::Valid(_) = placement {
+                return Some(position);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn fill(building: BuildingType, radius: f32) -> (Vec<(Vec2, Vec2)>, GameConfig, Entity) {
+        let config = GameConfig::default();
+        let island = World::new().spawn(());
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut buildings = Vec::new();
+        while let Some(position) = find_free_spot(
+            &mut rng,
+            building,
+            &config,
+            island,
+            Vec2::zero(),
+            radius,
+            &buildings,
+        ) {
+            buildings.push((position, building.size(&config)));
+            assert!(buildings.len() < 1000, "the island never filled up");
+        }
+        (buildings, config, island)
+    }
+
+    #[test]
+    fn spots_are_valid_placements() {
+        let (buildings, config, island) = fill(BuildingType::House, 60.);
+        assert!(buildings.len() > 1);
+        for (i, (position, _)) in buildings.iter().enumerate() {
+            let site = IslandSite {
+                island,
+                center: Vec2::zero(),
+                radius: 60.,
+                owned: true,
+                can_afford: true,
+            };
+            let others = buildings[..i].iter().chain(&buildings[i + 1..]).copied();
+            let placement = check_placement(
+                BuildingType::House,
+                *position,
+                &config,
+                std::iter::once(site),
+                others,
+            );
+            assert!(matches!(placement, Placement::Valid(_)), "{:?}", position);
+        }
+    }
+
+    #[test]
+    fn returns_none_when_the_island_is_full() {
+        let (buildings, config, island) = fill(BuildingType::House, 40.);
+        assert!(!buildings.is_empty());
+        // one building covering the whole island leaves no gap anywhere
+        let covered = [(Vec2::zero(), Vec2::splat(80.))];
+        let mut rng = StdRng::seed_from_u64(9);
+        for building in BuildingType::ALL.iter() {
+            let spot = find_free_spot(
+                &mut rng,
+                *building,
+                &config,
+                island,
+                Vec2::zero(),
+                40.,
+                &covered,
+            );
+            assert_eq!(spot, None, "{:?}", building);
+        }
+    }
+
+    #[test]
+    fn returns_none_when_the_island_is_too_small() {
+        let config = GameConfig::default();
+        let island = World::new().spawn(());
+        let mut rng = StdRng::seed_from_u64(1);
+        let spot = find_free_spot(
+            &mut rng,
+            BuildingType::Windbreak,
+            &config,
+            island,
+            Vec2::zero(),
+            5.,
+            &[],
+        );
+        assert_eq!(spot, None);
+    }
+
+    #[test]
+    fn docks_go_on_the_shore() {
+        let (buildings, _, _) = fill(BuildingType::Dock, 60.);
+        assert!(!buildings.is_empty());
+        for (position, size) in buildings {
+            assert!(position.length() + size.length() / 2. <= 60.);
+            assert!(60. - position.length() <= crate::boat::COAST_BAND + size.length() / 2.);
+        }
+    }
+}
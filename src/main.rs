@@ -1,7 +1,17 @@
+use std::time::Duration;
+
 use bevy::{
-    ecs::Stage, input::mouse::MouseButtonInput, math::vec3, prelude::*, render::camera::Camera,
+    app::{ScheduleRunnerPlugin, ScheduleRunnerSettings},
+    audio::AudioPlugin,
+    ecs::{Schedule, ShouldRun, Stage, StateStage, System},
+    input::mouse::MouseButtonInput,
+    math::vec3,
+    prelude::*,
+    wgpu::WgpuPlugin,
+    winit::WinitPlugin,
 };
 use bevy_prototype_lyon::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
 
 mod island;
 use island::*;
@@ -14,15 +24,150 @@ use resource::ResourcesPlugin;
 
 mod resource;
 
-const CAMERA_SPEED: f32 = 10.;
+mod ai;
+use ai::{AiDifficulty, AiPlugin};
+mod boat;
+use boat::BoatPlugin;
+mod building;
+use building::BuildingPlugin;
+mod clock;
+use clock::ClockPlugin;
+mod command;
+use command::GameCommandPlugin;
+mod config;
+use config::{ConfigPlugin, GameConfig};
+mod names;
+use names::NamesPlugin;
+mod faction;
+use faction::FactionsPlugin;
+mod feed;
+use feed::FeedPlugin;
+mod fog;
+use fog::FogPlugin;
+mod follow;
+use follow::{CameraFollow, FollowPlugin};
+mod layout;
+mod trade;
+use trade::TradePlugin;
+mod ui;
+use ui::UiPlugin;
+mod market;
+use market::MarketPlugin;
+mod menu;
+use menu::MenuPlugin;
+mod minimap;
+use minimap::MinimapPlugin;
+mod orders;
+use orders::OrdersPlugin;
+mod overlay;
+use overlay::OverlayPlugin;
+mod panel;
+use panel::IslandPanelPlugin;
+mod road;
+use road::RoadPlugin;
+mod stats;
+use stats::StatsPlugin;
+mod weather;
+use weather::WeatherPlugin;
+mod tooltip;
+use tooltip::TooltipPlugin;
+mod workshop;
+use workshop::WorkshopPlugin;
 
+#[derive(Default)]
 pub struct Materials {
     pub soil_material: Handle<ColorMaterial>,
     pub grass_material: Handle<ColorMaterial>,
     pub house_material: Handle<ColorMaterial>,
+    pub dock_material: Handle<ColorMaterial>,
+    pub boat_material: Handle<ColorMaterial>,
+    pub workshop_material: Handle<ColorMaterial>,
+    pub roof_material: Handle<ColorMaterial>,
+    pub road_material: Handle<ColorMaterial>,
+    pub windbreak_material: Handle<ColorMaterial>,
+    pub manor_roof_material: Handle<ColorMaterial>,
     pub transparent: Handle<ColorMaterial>,
     pub skin: Handle<ColorMaterial>,
+    pub selected_skin: Handle<ColorMaterial>,
+}
+pub struct MainCamera;
+
+/// Last known cursor position, in window pixels from the centre and in world units.
+#[derive(Debug, Default)]
+pub struct CursorPosition {
+    pub screen: Option<Vec2>,
+    pub world: Vec2,
+}
+
+/// Set by `--headless <seconds> [difficulty..]`: no window, every faction is played by the AI
+/// (with the given difficulties, in faction order) and the run ends after `duration` seconds.
+#[derive(Debug, Default)]
+pub struct Headless {
+    pub enabled: bool,
+    pub duration: f64,
+    pub difficulties: Vec<AiDifficulty>,
 }
+
+impl Headless {
+    fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.first().map(String::as_str) != Some("--headless") {
+            return Headless::default();
+        }
+        let duration = match args.get(1).map(|arg| arg.parse::<f64>()) {
+            Some(Ok(duration)) => duration,
+            Some(Err(e)) => panic!("--headless expects a duration in seconds: {}", e),
+            None => 600.,
+        };
+        let difficulties = args
+            .iter()
+            .skip(2)
+            .map(|arg| arg.parse().unwrap_or_else(|e: String| panic!("{}", e)))
+            .collect();
+        Headless {
+            enabled: true,
+            duration,
+            difficulties,
+        }
+    }
+}
+
+/// Seconds of simulated time per frame on a headless run.
+const HEADLESS_STEP: f32 = 1. / 60.;
+
+/// Time as the game sees it. It follows the clock, except on headless runs where every frame is
+/// one fixed step, so a run simulates as fast as the machine allows.
+#[derive(Debug, Default)]
+pub struct GameTime {
+    delta_seconds: f32,
+    seconds_since_startup: f64,
+}
+
+impl GameTime {
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta_seconds
+    }
+
+    pub fn seconds_since_startup(&self) -> f64 {
+        self.seconds_since_startup
+    }
+
+    /// Moves time on by one step of `delta` seconds.
+    pub fn advance(&mut self, delta: f32) {
+        self.delta_seconds = delta;
+        self.seconds_since_startup += delta as f64;
+    }
+}
+
+fn advance_game_time(time: Res<Time>, headless: Res<Headless>, mut game_time: ResMut<GameTime>) {
+    if headless.enabled {
+        game_time.advance(HEADLESS_STEP);
+    } else {
+        game_time.delta_seconds = time.delta_seconds();
+        game_time.seconds_since_startup = time.seconds_since_startup();
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Selected {
     pub items: Vec<Entity>,
@@ -31,27 +176,48 @@ pub struct Selected {
 fn setup(commands: &mut Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
     commands
         .spawn(Camera2dBundle::default())
+        .with(MainCamera)
         .insert_resource(Materials {
             soil_material: materials.add(Color::RED.into()),
             grass_material: materials.add(Color::GREEN.into()),
             house_material: materials.add(Color::BLUE.into()),
+            dock_material: materials.add(Color::rgb(0.5, 0.3, 0.1).into()),
+            boat_material: materials.add(Color::rgb(0.9, 0.8, 0.6).into()),
+            workshop_material: materials.add(Color::rgb(0.45, 0.45, 0.5).into()),
+            roof_material: materials.add(Color::rgb(0.6, 0.2, 0.1).into()),
+            road_material: materials.add(Color::rgb(0.55, 0.5, 0.4).into()),
+            windbreak_material: materials.add(Color::rgb(0.2, 0.45, 0.2).into()),
+            manor_roof_material: materials.add(Color::rgb(0.85, 0.65, 0.1).into()),
             skin: materials.add(Color::PINK.into()),
+            selected_skin: materials.add(Color::YELLOW.into()),
             transparent: materials.add(Color::rgba_linear(0., 0., 0., 0.5).into()),
         });
 }
 
-fn cam_move(keys: Res<Input<KeyCode>>, mut query: Query<&mut Transform, With<Camera>>) {
+fn cam_move(
+    keys: Res<Input<KeyCode>>,
+    config: Res<GameConfig>,
+    mut follow: ResMut<CameraFollow>,
+    mut query: Query<&mut Transform, With<MainCamera>>,
+) {
+    let manual = [KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D]
+        .iter()
+        .any(|key| keys.pressed(*key));
+    if manual {
+        follow.stop();
+    }
+    let camera_speed = config.camera_speed;
     for mut cam in query.iter_mut() {
         // Keyboard input
         if keys.pressed(KeyCode::A) {
-            cam.translation.x -= CAMERA_SPEED;
+            cam.translation.x -= camera_speed;
         } else if keys.pressed(KeyCode::D) {
-            cam.translation.x += CAMERA_SPEED;
+            cam.translation.x += camera_speed;
         }
         if keys.pressed(KeyCode::W) {
-            cam.translation.y += CAMERA_SPEED;
+            cam.translation.y += camera_speed;
         } else if keys.pressed(KeyCode::S) {
-            cam.translation.y -= CAMERA_SPEED;
+            cam.translation.y -= camera_speed;
         }
     }
 }
@@ -66,8 +232,9 @@ fn my_cursor_system(
     // need to get window dimensions
     wnds: Res<Windows>,
     // query to get camera transform
-    q_camera: Query<&Transform, With<Camera>>,
+    q_camera: Query<&Transform, With<MainCamera>>,
     mut q_hov: Query<(&mut Hovered, &GlobalTransform, &Parent)>,
+    mut cursor: ResMut<CursorPosition>,
 ) {
     // Mouse buttons
     // for ev in evr_mousebtn.iter(&ev_mousebtn) {
@@ -85,9 +252,13 @@ fn my_cursor_system(
     if let Some(ev) = evr_cursor.latest(&ev_cursor) {
         let wnd = wnds.get(ev.id).unwrap();
         let size = Vec2::new(wnd.width() as f32, wnd.height() as f32);
-        let p = ev.position - size / 2.0;
+        cursor.screen = Some(ev.position - size / 2.0);
+    }
+    // the camera can move without the cursor moving, so recompute every frame
+    if let Some(p) = cursor.screen {
         let pos_wld = camera_transform * p.extend(0.0).extend(1.0);
         let pos_wld = vec3(pos_wld.x, pos_wld.y, 0.);
+        cursor.world = Vec2::new(pos_wld.x, pos_wld.y);
 
         for (mut hovered, transform, p) in islands {
             let far = if (transform.translation.x - pos_wld.x).abs() > 100.
@@ -130,11 +301,16 @@ fn draw_hovered_islands(
     }
 }
 
-enum MyStages {
+#[derive(Debug, Clone, Copy)]
+pub enum MyStages {
     PreSetup,
+    Seeding,
     Islands,
+    Factions,
+    Resources,
     Homes,
     People,
+    Ui,
 }
 
 // impl MyStages {
@@ -145,41 +321,171 @@ impl MyStages {
     pub fn to_str(self) -> &'static str {
         match self {
             MyStages::PreSetup => "pre_setup",
+            MyStages::Seeding => "seeding",
             MyStages::Islands => "islands",
+            MyStages::Factions => "factions",
+            MyStages::Resources => "planting_resources",
             MyStages::Homes => "homes",
             MyStages::People => "people",
+            MyStages::Ui => "ui",
         }
     }
 }
 
+pub const APP_STATE_STAGE: &str = "app_state";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    MainMenu,
+    NewGameSetup,
+    /// Building the world, which takes a single frame.
+    Loading,
+    Playing,
+    Paused,
+    GameOver,
+}
+
+/// Run criteria for stages that only run while playing.
+pub fn playing(state: Res<State<AppState>>) -> ShouldRun {
+    if *state.current() == AppState::Playing {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Random source for world generation, seeded by `GameConfig::seed` so a seed always makes the
+/// same world.
+pub struct WorldRng(pub StdRng);
+
+/// The stages that build a new world while the game is loading, in order, so each sees what the
+/// ones before it spawned.
+fn world_generation() -> Schedule {
+    let mut schedule = Schedule::default();
+    for stage in [
+        MyStages::Seeding,
+        MyStages::Islands,
+        MyStages::Factions,
+        MyStages::Resources,
+        MyStages::Homes,
+        MyStages::People,
+        MyStages::Ui,
+    ]
+    .iter()
+    {
+        schedule.add_stage(stage.to_str(), SystemStage::parallel());
+    }
+    schedule
+}
+
+pub trait GameStates {
+    /// Adds a system to a world generation stage.
+    fn add_world_gen_system<S: System<In = (), Out = ()>>(
+        &mut self,
+        stage: MyStages,
+        system: S,
+    ) -> &mut Self;
+    /// Adds a gameplay system, which only runs while playing.
+    fn add_game_system<S: System<In = (), Out = ()>>(&mut self, system: S) -> &mut Self;
+}
+
+impl GameStates for AppBuilder {
+    fn add_world_gen_system<S: System<In = (), Out = ()>>(
+        &mut self,
+        stage: MyStages,
+        system: S,
+    ) -> &mut Self {
+        self.stage(APP_STATE_STAGE, |state_stage: &mut StateStage<AppState>| {
+            state_stage.enter_stage(AppState::Loading, |schedule: &mut Schedule| {
+                schedule.add_system_to_stage(stage.to_str(), system)
+            })
+        })
+    }
+
+    fn add_game_system<S: System<In = (), Out = ()>>(&mut self, system: S) -> &mut Self {
+        self.on_state_update(APP_STATE_STAGE, AppState::Playing, system)
+    }
+}
+
+fn seed_world(commands: &mut Commands, config: Res<GameConfig>) {
+    let rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    commands.insert_resource(WorldRng(rng));
+}
+
 fn main() {
-    App::build()
+    let headless = Headless::from_args();
+    let mut app = App::build();
+    if headless.enabled {
+        // no waiting between frames, game time advances a fixed step each one
+        app.add_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs(0)))
+            .add_plugins_with(DefaultPlugins, |group| {
+                group
+                    .disable::<WinitPlugin>()
+                    .disable::<WgpuPlugin>()
+                    .disable::<AudioPlugin>()
+            })
+            .add_plugin(ScheduleRunnerPlugin::default());
+    } else {
+        app.add_plugins(DefaultPlugins);
+    }
+
+    // headless runs skip the menus and start generating a world straight away
+    let first_state = if headless.enabled {
+        AppState::Loading
+    } else {
+        AppState::MainMenu
+    };
+    app.add_resource(headless)
+        .init_resource::<GameTime>()
+        .add_system_to_stage(stage::PRE_UPDATE, advance_game_time.system())
+        .add_plugin(ConfigPlugin)
         .init_resource::<Selected>()
-        .add_plugins(DefaultPlugins)
+        .init_resource::<CursorPosition>()
         .add_plugin(ShapePlugin)
         .add_startup_stage(
             MyStages::PreSetup.to_str(),
             SystemStage::single(setup.system()),
         )
-        .add_startup_stage_after(
-            MyStages::PreSetup.to_str(),
-            MyStages::Islands.to_str(),
-            SystemStage::parallel(),
-        )
-        .add_startup_stage_after(
-            MyStages::Islands.to_str(),
-            MyStages::Homes.to_str(),
-            SystemStage::single(build_house.system()),
-        )
-        .add_startup_stage_after(
-            MyStages::Homes.to_str(),
-            MyStages::People.to_str(),
-            SystemStage::parallel(),
+        .add_resource(State::new(first_state))
+        .add_stage_after(
+            stage::UPDATE,
+            APP_STATE_STAGE,
+            StateStage::<AppState>::default()
+                .with_enter_stage(AppState::Loading, world_generation()),
         )
-        .add_system(cam_move.system())
+        .add_world_gen_system(MyStages::Seeding, seed_world.system())
+        .add_world_gen_system(MyStages::Homes, build_house.system())
+        .add_game_system(cam_move.system())
         .add_system(my_cursor_system.system())
+        .add_plugin(MenuPlugin)
         .add_plugin(IslandsPlugin)
+        .add_plugin(HousePlugin)
         .add_plugin(PeoplePlugin)
         .add_plugin(ResourcesPlugin)
+        .add_plugin(NamesPlugin)
+        .add_plugin(FactionsPlugin)
+        .add_plugin(GameCommandPlugin)
+        .add_plugin(AiPlugin)
+        .add_plugin(BoatPlugin)
+        .add_plugin(TradePlugin)
+        .add_plugin(MarketPlugin)
+        .add_plugin(UiPlugin)
+        .add_plugin(FeedPlugin)
+        .add_plugin(IslandPanelPlugin)
+        .add_plugin(StatsPlugin)
+        .add_plugin(BuildingPlugin)
+        .add_plugin(WorkshopPlugin)
+        .add_plugin(RoadPlugin)
+        .add_plugin(WeatherPlugin)
+        .add_plugin(ClockPlugin)
+        .add_plugin(OrdersPlugin)
+        .add_plugin(MinimapPlugin)
+        .add_plugin(OverlayPlugin)
+        .add_plugin(TooltipPlugin)
+        .add_plugin(FollowPlugin)
+        .add_plugin(FogPlugin)
         .run();
 }
@@ -0,0 +1,328 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{
+    building::BuildingType,
+    command::GameCommand,
+    config::GameConfig,
+    faction::{Factions, Owner},
+    house::House,
+    island::{Island, IslandNR},
+    panel::SelectedIsland,
+    person::Person,
+    resource::NaturalResourceType,
+    stats::count_population,
+    weather::{Damage, REPAIR_WOOD},
+    workshop::{Ware, Workshop, INPUT_BATCHES},
+    GameStates, GameTime,
+};
+
+const PRICE_UPDATE_INTERVAL: f32 = 5.;
+const PRICE_HISTORY_LEN: usize = 60;
+/// Share of the price paid out when selling; the rest is the market's cut.
+pub const SELL_RATE: f32 = 0.9;
+/// How far a price moves toward its supply and demand target on each update.
+const PRICE_SMOOTHING: f32 = 0.2;
+const MIN_PRICE_FACTOR: f32 = 0.25;
+const MAX_PRICE_FACTOR: f32 = 4.;
+
+/// Gold is the currency, so its price is always 1.
+pub const CURRENCY: NaturalResourceType = NaturalResourceType::Gold;
+const RESOURCE_COUNT: usize = NaturalResourceType::ALL.len();
+
+fn base_price(nr_type: NaturalResourceType) -> f32 {
+    match nr_type {
+        NaturalResourceType::Water => 0.5,
+        NaturalResourceType::Wood => 1.,
+        NaturalResourceType::Coal => 2.,
+        NaturalResourceType::Iron => 3.,
+        NaturalResourceType::Gold => 1.,
+    }
+}
+
+/// Stock each resident wants to have around.
+fn per_capita_demand(nr_type: NaturalResourceType) -> f32 {
+    match nr_type {
+        NaturalResourceType::Water => 2.,
+        NaturalResourceType::Wood => 1.,
+        NaturalResourceType::Coal | NaturalResourceType::Iron => 0.5,
+        NaturalResourceType::Gold => 0.,
+    }
+}
+
+/// An island's prices in Gold, one per resource in the order of `NaturalResourceType::ALL`.
+#[derive(Debug, Clone)]
+pub struct Market {
+    pub prices: [f32; RESOURCE_COUNT],
+    /// Past prices, oldest first.
+    pub history: VecDeque<[f32; RESOURCE_COUNT]>,
+}
+
+impl Default for Market {
+    fn default() -> Self {
+        let mut prices = [0.; RESOURCE_COUNT];
+        for (i, nr_type) in NaturalResourceType::ALL.iter().enumerate() {
+            prices[i] = base_price(*nr_type);
+        }
+        Self {
+            prices,
+            history: VecDeque::new(),
+        }
+    }
+}
+
+fn index_of(nr_type: NaturalResourceType) -> usize {
+    NaturalResourceType::ALL
+        .iter()
+        .position(|t| *t == nr_type)
+        .unwrap()
+}
+
+impl Market {
+    pub fn price(&self, nr_type: NaturalResourceType) -> f32 {
+        self.prices[index_of(nr_type)]
+    }
+
+    /// Gold it takes to buy `amount`.
+    pub fn buy_cost(&self, nr_type: NaturalResourceType, amount: u32) -> u32 {
+        (self.price(nr_type) * amount as f32).ceil() as u32
+    }
+
+    /// Gold paid for selling `amount`.
+    pub fn sell_value(&self, nr_type: NaturalResourceType, amount: u32) -> u32 {
+        (self.price(nr_type) * amount as f32 * SELL_RATE).floor() as u32
+    }
+
+    pub fn price_history(&self, nr_type: NaturalResourceType) -> impl Iterator<Item = f32> + '_ {
+        let i = index_of(nr_type);
+        self.history.iter().map(move |prices| prices[i])
+    }
+}
+
+/// Resource picked for buying and selling in the island panel.
+pub struct MarketSelection(pub NaturalResourceType);
+
+impl Default for MarketSelection {
+    fn default() -> Self {
+        MarketSelection(NaturalResourceType::Wood)
+    }
+}
+
+pub struct MarketTimer(Timer);
+
+fn track_new_markets(
+    commands: &mut Commands,
+    query: Query<Entity, (With<Island>, Without<Market>)>,
+) {
+    for island in query.iter() {
+        commands.insert_one(island, Market::default());
+    }
+}
+
+/// What an island's buildings are waiting for: Wood to repair storm damage, inputs its
+/// workshops are short of and, once every house is full, the cost of another house.
+fn building_needs(
+    island: Entity,
+    config: &GameConfig,
+    houses_query: &Query<&House>,
+    damage_query: &Query<(&House, &Damage)>,
+    workshops_query: &Query<&Workshop>,
+) -> [u32; RESOURCE_COUNT] {
+    let mut needs = [0; RESOURCE_COUNT];
+    for (_, damage) in damage_query
+        .iter()
+        .filter(|(house, _)| house.island == island)
+    {
+        needs[index_of(NaturalResourceType::Wood)] += damage.0 * REPAIR_WOOD;
+    }
+    for workshop in workshops_query
+        .iter()
+        .filter(|workshop| workshop.island == island)
+    {
+        for (ware, amount) in workshop.kind.recipe().inputs {
+            if let Ware::Raw(nr_type) = ware {
+                let held = workshop.input.get(&ware).copied().unwrap_or(0);
+                needs[index_of(nr_type)] += (amount * INPUT_BATCHES).saturating_sub(held);
+            }
+        }
+    }
+    let crowded = !houses_query
+        .iter()
+        .any(|house| house.island == island && house.has_room());
+    if crowded {
+        for (nr_type, amount) in BuildingType::House.cost(config) {
+            needs[index_of(nr_type)] += amount;
+        }
+    }
+    needs
+}
+
+/// Moves every price toward what supply (the island's stock) and demand (what its residents use
+/// and what its buildings are waiting for) call for.
+fn update_prices(
+    time: Res<GameTime>,
+    config: Res<GameConfig>,
+    mut timer: ResMut<MarketTimer>,
+    houses_query: Query<&House>,
+    damage_query: Query<(&House, &Damage)>,
+    workshops_query: Query<&Workshop>,
+    people_query: Query<&Person>,
+    mut islands_query: Query<(Entity, &IslandNR, &mut Market)>,
+) {
+    if !timer.0.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+
+    for (island, stock, mut market) in islands_query.iter_mut() {
+        let population = count_population(island, &houses_query, &people_query) as f32;
+        let building_needs = building_needs(
+            island,
+            &config,
+            &houses_query,
+            &damage_query,
+            &workshops_query,
+        );
+        for (i, nr_type) in NaturalResourceType::ALL.iter().enumerate() {
+            if *nr_type == CURRENCY {
+                continue;
+            }
+            let supply = stock.0.get(nr_type).copied().unwrap_or(0) as f32;
+            let demand = population * per_capita_demand(*nr_type) + building_needs[i] as f32;
+            let factor = ((demand + 1.) / (supply + 1.))
+                .max(MIN_PRICE_FACTOR)
+                .min(MAX_PRICE_FACTOR);
+            let target = base_price(*nr_type) * factor;
+            market.prices[i] += (target - market.prices[i]) * PRICE_SMOOTHING;
+        }
+        let prices = market.prices;
+        market.history.push_back(prices);
+        if market.history.len() > PRICE_HISTORY_LEN {
+            market.history.pop_front();
+        }
+    }
+}
+
+/// Buys and sells at island markets for any faction, paying and getting paid in Gold.
+fn handle_market_commands(
+    events: Res<Events<GameCommand>>,
+    mut event_reader: Local<EventReader<GameCommand>>,
+    mut islands_query: Query<(&Owner, &Market, &mut IslandNR)>,
+) {
+    for ev in event_reader.iter(&events) {
+        let (faction, island, resource, amount) = match ev {
+            GameCommand::MarketTrade {
+                faction,
+                island,
+                resource,
+                amount,
+            } => (*faction, *island, *resource, *amount),
+            _ => continue,
+        };
+        let (owner, market, mut stock) = match islands_query.get_mut(island) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        if owner.0 != Some(faction) || resource == CURRENCY || amount == 0 {
+            continue;
+        }
+        let gold = stock.0.get(&CURRENCY).copied().unwrap_or(0);
+        let held = stock.0.get(&resource).copied().unwrap_or(0);
+        if amount > 0 {
+            let bought = amount as u32;
+            let cost = market.buy_cost(resource, bought);
+            if cost > gold {
+                println!("Not enough Gold to buy {} {:?}", bought, resource);
+                continue;
+            }
+            stock.0.insert(CURRENCY, gold - cost);
+            stock.0.insert(resource, held + bought);
+        } else {
+            let sold = amount.abs() as u32;
+            if sold > held {
+                println!("Not enough {:?} to sell {}", resource, sold);
+                continue;
+            }
+            let value = market.sell_value(resource, sold);
+            if value == 0 {
+                println!("{} {:?} isn't worth any Gold yet", sold, resource);
+                continue;
+            }
+            stock.0.insert(resource, held - sold);
+            stock.0.insert(CURRENCY, gold + value);
+        }
+    }
+}
+
+/// With an island panel open, `M` picks the resource to trade and `+`/`-` buy or sell one of
+/// it (ten with shift).
+fn market_controls(
+    keys: Res<Input<KeyCode>>,
+    factions: Res<Factions>,
+    selected_island: Res<SelectedIsland>,
+    mut selection: ResMut<MarketSelection>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+) {
+    let island = match selected_island.0 {
+        Some(island) => island,
+        None => return,
+    };
+    if keys.just_pressed(KeyCode::M) {
+        let tradable: Vec<NaturalResourceType> = NaturalResourceType::ALL
+            .iter()
+            .copied()
+            .filter(|nr_type| *nr_type != CURRENCY)
+            .collect();
+        let next = tradable
+            .iter()
+            .position(|nr_type| *nr_type == selection.0)
+            .map_or(0, |i| (i + 1) % tradable.len());
+        selection.0 = tradable[next];
+    }
+    let lot = if keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift) {
+        10
+    } else {
+        1
+    };
+    let amount = if keys.just_pressed(KeyCode::Equals) || keys.just_pressed(KeyCode::Add) {
+        lot
+    } else if keys.just_pressed(KeyCode::Minus) || keys.just_pressed(KeyCode::Subtract) {
+        -lot
+    } else {
+        return;
+    };
+    game_commands.send(GameCommand::MarketTrade {
+        faction: factions.player,
+        island,
+        resource: selection.0,
+        amount,
+    });
+}
+
+/// Prices as a row of bars, lowest to highest over the shown history.
+pub fn sparkline(values: impl Iterator<Item = f32>) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let values: Vec<f32> = values.collect();
+    let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let range = (max - min).max(f32::EPSILON);
+    values
+        .iter()
+        .map(|value| BARS[(((value - min) / range) * 7.).round() as usize])
+        .collect()
+}
+
+pub struct MarketPlugin;
+impl Plugin for MarketPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<MarketSelection>()
+            .add_resource(MarketTimer(Timer::from_seconds(
+                PRICE_UPDATE_INTERVAL,
+                true,
+            )))
+            .add_game_system(track_new_markets.system())
+            .add_game_system(update_prices.system())
+            .add_game_system(market_controls.system())
+            .add_game_system(handle_market_commands.system());
+    }
+}
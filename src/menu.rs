@@ -0,0 +1,356 @@
+use bevy::{app::AppExit, prelude::*};
+use rand::Rng;
+
+use crate::{
+    config::GameConfig,
+    faction::Factions,
+    ui::{text_bundle, UiFont},
+    AppState, GameStates, Headless, Materials, APP_STATE_STAGE,
+};
+
+const MENU_LINES: usize = 12;
+const MENU_LINE_HEIGHT: f32 = 22.;
+/// Islands fill rings of 6, 12, 18..., so this is the centre and the first two rings.
+const MAX_ISLANDS: usize = 19;
+const SETUP_FIELDS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceDensity {
+    Sparse,
+    Normal,
+    Rich,
+}
+
+impl ResourceDensity {
+    fn next(self) -> Self {
+        match self {
+            ResourceDensity::Sparse => ResourceDensity::Normal,
+            _ => ResourceDensity::Rich,
+        }
+    }
+
+    fn previous(self) -> Self {
+        match self {
+            ResourceDensity::Rich => ResourceDensity::Normal,
+            _ => ResourceDensity::Sparse,
+        }
+    }
+
+    /// Scales the configured range of resource clusters per island.
+    fn clusters(self, (min, max): (usize, usize)) -> (usize, usize) {
+        match self {
+            ResourceDensity::Sparse => {
+                let min = (min / 2).max(1);
+                (min, (max / 2).max(min + 1))
+            }
+            ResourceDensity::Normal => (min, max),
+            ResourceDensity::Rich => (min * 2, max * 2),
+        }
+    }
+}
+
+/// What the new game screen has picked so far.
+#[derive(Debug)]
+pub struct NewGameSettings {
+    pub seed: u64,
+    pub islands: usize,
+    pub density: ResourceDensity,
+    /// The field the arrow keys change.
+    selected: usize,
+}
+
+impl Default for NewGameSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            islands: 1,
+            density: ResourceDensity::Normal,
+            selected: 0,
+        }
+    }
+}
+
+/// Why the game ended, shown on the game over screen.
+#[derive(Debug, Default)]
+pub struct Outcome(pub String);
+
+/// Root node and every text line of the current menu screen.
+pub struct MenuPart;
+pub struct MenuLine(pub usize);
+
+fn spawn_menu(commands: &mut Commands, font: Res<UiFont>, materials: Res<Materials>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(
+                    Val::Px(420.),
+                    Val::Px(MENU_LINES as f32 * MENU_LINE_HEIGHT + 16.),
+                ),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Percent(35.),
+                    top: Val::Percent(25.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: Rect::all(Val::Px(8.)),
+                ..Default::default()
+            },
+            material: materials.transparent.clone(),
+            ..Default::default()
+        })
+        .with(MenuPart)
+        .with_children(|parent| {
+            for i in 0..MENU_LINES {
+                let mut line = text_bundle(&font, "", 18.);
+                line.style.size = Size::new(Val::Auto, Val::Px(MENU_LINE_HEIGHT));
+                parent.spawn(line).with(MenuPart).with(MenuLine(i));
+            }
+        });
+}
+
+fn despawn_menu(commands: &mut Commands, query: Query<Entity, With<MenuPart>>) {
+    for entity in query.iter() {
+        commands.despawn(entity);
+    }
+}
+
+fn show_lines(lines: &[String], query: &mut Query<(&MenuLine, &mut Text)>) {
+    for (line, mut text) in query.iter_mut() {
+        let value = lines.get(line.0).map_or("", String::as_str);
+        if text.value != value {
+            text.value = value.to_string();
+        }
+    }
+}
+
+fn main_menu(
+    keys: Res<Input<KeyCode>>,
+    mut state: ResMut<State<AppState>>,
+    mut app_exit: ResMut<Events<AppExit>>,
+    mut lines_query: Query<(&MenuLine, &mut Text)>,
+) {
+    show_lines(
+        &[
+            "Islands".to_string(),
+            String::new(),
+            "Enter  new game".to_string(),
+            "Esc    quit".to_string(),
+        ],
+        &mut lines_query,
+    );
+    if keys.just_pressed(KeyCode::Return) {
+        state.set_next(AppState::NewGameSetup).unwrap();
+    } else if keys.just_pressed(KeyCode::Escape) {
+        app_exit.send(AppExit);
+    }
+}
+
+/// Starts the new game screen from what the config asks for.
+fn reset_settings(config: Res<GameConfig>, mut settings: ResMut<NewGameSettings>) {
+    *settings = NewGameSettings {
+        seed: config
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen_range(0..1_000_000)),
+        islands: config.islands.max(1).min(MAX_ISLANDS),
+        ..Default::default()
+    };
+}
+
+/// Up and down pick a field, left and right change it. Enter writes the choices into the config
+/// and generates the world.
+fn new_game_setup(
+    keys: Res<Input<KeyCode>>,
+    mut settings: ResMut<NewGameSettings>,
+    mut config: ResMut<GameConfig>,
+    mut state: ResMut<State<AppState>>,
+    mut lines_query: Query<(&MenuLine, &mut Text)>,
+) {
+    if keys.just_pressed(KeyCode::Up) {
+        settings.selected = (settings.selected + SETUP_FIELDS - 1) % SETUP_FIELDS;
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        settings.selected = (settings.selected + 1) % SETUP_FIELDS;
+    }
+    let step: i64 = if keys.just_pressed(KeyCode::Right) {
+        1
+    } else if keys.just_pressed(KeyCode::Left) {
+        -1
+    } else {
+        0
+    };
+    if step != 0 {
+        match settings.selected {
+            0 => settings.seed = (settings.seed as i64 + step).max(0) as u64,
+            1 => {
+                settings.islands = (settings.islands as i64 + step)
+                    .max(1)
+                    .min(MAX_ISLANDS as i64) as usize
+            }
+            _ => {
+                settings.density = if step > 0 {
+                    settings.density.next()
+                } else {
+                    settings.density.previous()
+                }
+            }
+        }
+    }
+    if keys.just_pressed(KeyCode::R) {
+        settings.seed = rand::thread_rng().gen_range(0..1_000_000);
+    }
+
+    let marker = |field: usize| if settings.selected == field { ">" } else { " " };
+    show_lines(
+        &[
+            "New game".to_string(),
+            String::new(),
+            format!("{} Seed: {}", marker(0), settings.seed),
+            format!("{} Islands: {}", marker(1), settings.islands),
+            format!("{} Resources: {:?}", marker(2), settings.density),
+            String::new(),
+            "Up/Down pick, Left/Right change".to_string(),
+            "R  random seed".to_string(),
+            "Enter  start, Esc  back".to_string(),
+        ],
+        &mut lines_query,
+    );
+
+    if keys.just_pressed(KeyCode::Return) {
+        config.seed = Some(settings.seed);
+        config.islands = settings.islands;
+        config.resource_clusters = settings.density.clusters(config.resource_clusters);
+        println!(
+            "Generating {} islands from seed {} with {:?} resources",
+            settings.islands, settings.seed, settings.density
+        );
+        state.set_next(AppState::Loading).unwrap();
+    } else if keys.just_pressed(KeyCode::Escape) {
+        state.set_next(AppState::MainMenu).unwrap();
+    }
+}
+
+/// The world was generated on entering `Loading`, so play starts on the next frame.
+fn finish_loading(mut state: ResMut<State<AppState>>) {
+    state.set_next(AppState::Playing).unwrap();
+}
+
+/// `P` pauses the game.
+fn pause_controls(keys: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if keys.just_pressed(KeyCode::P) {
+        // the game may have just ended this frame
+        let _ = state.set_next(AppState::Paused);
+    }
+}
+
+fn paused_menu(
+    keys: Res<Input<KeyCode>>,
+    mut state: ResMut<State<AppState>>,
+    mut outcome: ResMut<Outcome>,
+    mut lines_query: Query<(&MenuLine, &mut Text)>,
+) {
+    show_lines(
+        &[
+            "Paused".to_string(),
+            String::new(),
+            "P  resume".to_string(),
+            "Q  end the game".to_string(),
+        ],
+        &mut lines_query,
+    );
+    if keys.just_pressed(KeyCode::P) || keys.just_pressed(KeyCode::Escape) {
+        state.set_next(AppState::Playing).unwrap();
+    } else if keys.just_pressed(KeyCode::Q) {
+        outcome.0 = "You ended the game".to_string();
+        state.set_next(AppState::GameOver).unwrap();
+    }
+}
+
+/// The game is lost once the player's faction has no islands and nobody left. Headless runs have
+/// no player, so they only end when their time is up.
+fn check_game_over(
+    headless: Res<Headless>,
+    factions: Res<Factions>,
+    mut state: ResMut<State<AppState>>,
+    mut outcome: ResMut<Outcome>,
+    mut settled: Local<bool>,
+) {
+    if headless.enabled {
+        return;
+    }
+    let player = match factions.get(factions.player) {
+        Some(player) => player,
+        None => return,
+    };
+    // faction stats start at zero until they are first counted
+    if player.population > 0 {
+        *settled = true;
+    }
+    if !*settled || player.population > 0 || player.islands > 0 {
+        return;
+    }
+    println!("{} has no islands and no people left", player.name);
+    outcome.0 = format!("{} has no islands and no people left", player.name);
+    let _ = state.set_next(AppState::GameOver);
+}
+
+fn game_over_menu(
+    keys: Res<Input<KeyCode>>,
+    headless: Res<Headless>,
+    factions: Res<Factions>,
+    outcome: Res<Outcome>,
+    mut app_exit: ResMut<Events<AppExit>>,
+    mut lines_query: Query<(&MenuLine, &mut Text)>,
+) {
+    let mut standings: Vec<_> = factions.list.iter().collect();
+    standings.sort_by(|a, b| b.score.cmp(&a.score));
+    let mut lines = vec!["Game over".to_string(), outcome.0.clone(), String::new()];
+    lines.extend(standings.iter().take(MENU_LINES - 5).map(|faction| {
+        format!(
+            "{}: score {}, {} islands, {} people",
+            faction.name, faction.score, faction.islands, faction.population
+        )
+    }));
+    lines.push(String::new());
+    lines.push("Esc  quit".to_string());
+    show_lines(&lines, &mut lines_query);
+    // nobody is there to read the screen on a headless run
+    if keys.just_pressed(KeyCode::Escape) || headless.enabled {
+        app_exit.send(AppExit);
+    }
+}
+
+pub struct MenuPlugin;
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<NewGameSettings>()
+            .init_resource::<Outcome>()
+            .add_game_system(pause_controls.system())
+            .add_game_system(check_game_over.system())
+            .on_state_update(APP_STATE_STAGE, AppState::Loading, finish_loading.system())
+            .on_state_enter(
+                APP_STATE_STAGE,
+                AppState::NewGameSetup,
+                reset_settings.system(),
+            )
+            .on_state_update(APP_STATE_STAGE, AppState::MainMenu, main_menu.system())
+            .on_state_update(
+                APP_STATE_STAGE,
+                AppState::NewGameSetup,
+                new_game_setup.system(),
+            )
+            .on_state_update(APP_STATE_STAGE, AppState::Paused, paused_menu.system())
+            .on_state_update(APP_STATE_STAGE, AppState::GameOver, game_over_menu.system());
+        for state in [
+            AppState::MainMenu,
+            AppState::NewGameSetup,
+            AppState::Paused,
+            AppState::GameOver,
+        ]
+        .iter()
+        {
+            app.on_state_enter(APP_STATE_STAGE, *state, spawn_menu.system())
+                .on_state_exit(APP_STATE_STAGE, *state, despawn_menu.system());
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use bevy::{
+    math::vec3,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    faction::Factions,
+    fog::{FogOfWar, Sight},
+    follow::CameraFollow,
+    house::House,
+    island::{Island, Size as IslandSize},
+    person::Person,
+    resource::{NaturalResource, NaturalResourceMaterials},
+    CursorPosition, GameStates, MainCamera, Materials, MyStages,
+};
+
+const MINIMAP_SIZE: f32 = 200.;
+const MINIMAP_MARGIN: f32 = 10.;
+/// Half the side of the square of the world shown on the minimap.
+const WORLD_HALF_EXTENT: f32 = 1200.;
+const SCALE: f32 = MINIMAP_SIZE / (2. * WORLD_HALF_EXTENT);
+const DOT_SIZE: f32 = 2.;
+
+pub struct MinimapRoot;
+pub struct MinimapViewport;
+/// Transparent UI node over the minimap, so clicks on it don't reach the world.
+pub struct MinimapArea;
+pub struct MinimapDot;
+
+pub struct MinimapMaterials {
+    pub background: Handle<ColorMaterial>,
+    pub outline: Handle<ColorMaterial>,
+    pub viewport: Handle<ColorMaterial>,
+}
+
+fn minimap_center(window: &Window) -> Vec2 {
+    Vec2::new(
+        window.width() as f32 / 2. - MINIMAP_MARGIN - MINIMAP_SIZE / 2.,
+        -(window.height() as f32) / 2. + MINIMAP_MARGIN + MINIMAP_SIZE / 2.,
+    )
+}
+
+fn to_minimap(world: Vec2) -> Vec2 {
+    world
+        .max(Vec2::splat(-WORLD_HALF_EXTENT))
+        .min(Vec2::splat(WORLD_HALF_EXTENT))
+        * SCALE
+}
+
+fn setup_minimap(
+    commands: &mut Commands,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    camera_query: Query<Entity, With<MainCamera>>,
+) {
+    let minimap_materials = MinimapMaterials {
+        background: color_materials.add(Color::rgba(0., 0.1, 0.3, 0.8).into()),
+        outline: color_materials.add(Color::GREEN.into()),
+        viewport: color_materials.add(Color::WHITE.into()),
+    };
+
+    let root = commands
+        .spawn(GeometryBuilder::build_as(
+            &shapes::Rectangle {
+                width: MINIMAP_SIZE,
+                height: MINIMAP_SIZE,
+                origin: shapes::RectangleOrigin::Center,
+            },
+            minimap_materials.background.clone(),
+            TessellationMode::Fill(FillOptions::default()),
+            Transform::from_translation(vec3(0., 0., -2.)),
+        ))
+        .with(MinimapRoot)
+        .current_entity()
+        .unwrap();
+    if let Some(camera) = camera_query.iter().next() {
+        commands.push_children(camera, &[root]);
+    }
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(MINIMAP_SIZE), Val::Px(MINIMAP_SIZE)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(MINIMAP_MARGIN),
+                    bottom: Val::Px(MINIMAP_MARGIN),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: color_materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with(Interaction::default())
+        .with(MinimapArea)
+        .insert_resource(minimap_materials);
+}
+
+fn place_minimap(windows: Res<Windows>, mut root_query: Query<&mut Transform, With<MinimapRoot>>) {
+    if let Some(window) = windows.get_primary() {
+        let center = minimap_center(window);
+        for mut transform in root_query.iter_mut() {
+            transform.translation.x = center.x;
+            transform.translation.y = center.y;
+        }
+    }
+}
+
+/// Keeps one minimap marker per island, house, resource and person, adding and removing
+/// markers as the world changes.
+fn sync_minimap_dots(
+    commands: &mut Commands,
+    materials: Res<Materials>,
+    nr_materials: Res<NaturalResourceMaterials>,
+    minimap_materials: Res<MinimapMaterials>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    mut dots: Local<HashMap<Entity, Entity>>,
+    root_query: Query<Entity, With<MinimapRoot>>,
+    mut dots_query: Query<&mut Transform, With<MinimapDot>>,
+    islands_query: Query<(Entity, &GlobalTransform, &IslandSize), With<Island>>,
+    houses_query: Query<(Entity, &GlobalTransform), With<House>>,
+    resources_query: Query<(Entity, &GlobalTransform, &Visible, &NaturalResource)>,
+    people_query: Query<(Entity, &GlobalTransform, &Visible), With<Person>>,
+) {
+    let root = match root_query.iter().next() {
+        Some(root) => root,
+        None => return,
+    };
+    let mut seen = HashSet::default();
+    let mut new_dots = Vec::new();
+
+    let mut sync = |source: Entity,
+                    world: &GlobalTransform,
+                    z: f32,
+                    spawn: &mut dyn FnMut(&mut Commands, Transform) -> Entity| {
+        seen.insert(source);
+        let position = to_minimap(world.translation.truncate());
+        let translation = vec3(position.x, position.y, z);
+        match dots.get(&source).map(|dot| dots_query.get_mut(*dot)) {
+            Some(Ok(mut transform)) => {
+                if transform.translation != translation {
+                    transform.translation = translation;
+                }
+            }
+            Some(Err(_)) => {}
+            None => {
+                let dot = spawn(commands, Transform::from_translation(translation));
+                dots.insert(source, dot);
+                new_dots.push(dot);
+            }
+        }
+    };
+    let dot_sprite = |material: Handle<ColorMaterial>| {
+        move |commands: &mut Commands, transform: Transform| {
+            commands
+                .spawn(SpriteBundle {
+                    material: material.clone(),
+                    sprite: Sprite::new(Vec2::splat(DOT_SIZE)),
+                    transform,
+                    ..Default::default()
+                })
+                .with(MinimapDot)
+                .current_entity()
+                .unwrap()
+        }
+    };
+
+    // only what the player has discovered gets a dot, the fog hides the rest
+    let player = factions.player;
+    for (island, transform, size) in islands_query.iter() {
+        if !fog.knows_island(player, island) {
+            continue;
+        }
+        let outline = minimap_materials.outline.clone();
+        let radius = (size.width / 2. * SCALE).max(DOT_SIZE);
+        sync(island, transform, 0.1, &mut |commands, transform| {
+            commands
+                .spawn(GeometryBuilder::build_as(
+                    &shapes::Circle {
+                        radius,
+                        center: Vec2::zero(),
+                    },
+                    outline.clone(),
+                    TessellationMode::Stroke(StrokeOptions::default()),
+                    transform,
+                ))
+                .with(MinimapDot)
+                .current_entity()
+                .unwrap()
+        });
+    }
+    for (house, transform) in houses_query.iter() {
+        if fog.sight(player, transform.translation.truncate()) == Sight::Unexplored {
+            continue;
+        }
+        sync(
+            house,
+            transform,
+            0.2,
+            &mut dot_sprite(materials.house_material.clone()),
+        );
+    }
+    for (resource, transform, visible, nr) in resources_query.iter() {
+        if !visible.is_visible {
+            continue;
+        }
+        sync(
+            resource,
+            transform,
+            0.3,
+            &mut dot_sprite(nr_materials.get(nr.0)),
+        );
+    }
+    for (person, transform, visible) in people_query.iter() {
+        if !visible.is_visible {
+            continue;
+        }
+        sync(
+            person,
+            transform,
+            0.4,
+            &mut dot_sprite(materials.skin.clone()),
+        );
+    }
+
+    if !new_dots.is_empty() {
+        commands.push_children(root, &new_dots);
+    }
+    dots.retain(|source, dot| {
+        let keep = seen.contains(source);
+        if !keep {
+            commands.despawn(*dot);
+        }
+        keep
+    });
+}
+
+fn draw_minimap_viewport(
+    commands: &mut Commands,
+    windows: Res<Windows>,
+    minimap_materials: Res<MinimapMaterials>,
+    mut last_window_size: Local<Vec2>,
+    root_query: Query<Entity, With<MinimapRoot>>,
+    camera_query: Query<&Transform, With<MainCamera>>,
+    mut viewport_query: Query<(Entity, &mut Transform), With<MinimapViewport>>,
+) {
+    let (window, root, camera) = match (
+        windows.get_primary(),
+        root_query.iter().next(),
+        camera_query.iter().next(),
+    ) {
+        (Some(window), Some(root), Some(camera)) => (window, root, camera),
+        _ => return,
+    };
+    let position = to_minimap(camera.translation.truncate());
+    let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+
+    if *last_window_size == window_size {
+        for (_, mut transform) in viewport_query.iter_mut() {
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+        return;
+    }
+    // the frame's size follows the window, so it is rebuilt on resize
+    *last_window_size = window_size;
+    for (viewport, _) in viewport_query.iter_mut() {
+        commands.despawn(viewport);
+    }
+    let viewport = commands
+        .spawn(GeometryBuilder::build_as(
+            &shapes::Rectangle {
+                width: window_size.x * SCALE,
+                height: window_size.y * SCALE,
+                origin: shapes::RectangleOrigin::Center,
+            },
+            minimap_materials.viewport.clone(),
+            TessellationMode::Stroke(StrokeOptions::default()),
+            Transform::from_translation(vec3(position.x, position.y, 0.5)),
+        ))
+        .with(MinimapViewport)
+        .current_entity()
+        .unwrap();
+    commands.push_children(root, &[viewport]);
+}
+
+fn handle_minimap_clicks(
+    windows: Res<Windows>,
+    cursor: Res<CursorPosition>,
+    mouse: Res<Input<MouseButton>>,
+    mut follow: ResMut<CameraFollow>,
+    area_query: Query<&Interaction, With<MinimapArea>>,
+    mut camera_query: Query<&mut Transform, With<MainCamera>>,
+) {
+    let over_minimap = area_query
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if !over_minimap || !mouse.pressed(MouseButton::Left) {
+        return;
+    }
+    let (window, screen) = match (windows.get_primary(), cursor.screen) {
+        (Some(window), Some(screen)) => (window, screen),
+        _ => return,
+    };
+    let world = (screen - minimap_center(window)) / SCALE;
+    follow.stop();
+    for mut camera in camera_query.iter_mut() {
+        camera.translation.x = world.x;
+        camera.translation.y = world.y;
+    }
+}
+
+pub struct MinimapPlugin;
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_world_gen_system(MyStages::Ui, setup_minimap.system())
+            .add_game_system(place_minimap.system())
+            .add_game_system(sync_minimap_dots.system())
+            .add_game_system(draw_minimap_viewport.system())
+            .add_game_system(handle_minimap_clicks.system());
+    }
+}
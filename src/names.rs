@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use rand::{prelude::SliceRandom, rngs::StdRng, Rng, SeedableRng};
+
+use crate::{config::GameConfig, GameStates, MyStages};
+
+const MAX_ATTEMPTS: usize = 50;
+
+const ISLAND_SAMPLES: [&str; 16] = [
+    "Ocor", "Tarvos", "Melina", "Korath", "Valdun", "Isara", "Brenmor", "Skelda", "Aruna",
+    "Thessa", "Norvik", "Calyx", "Mirath", "Oloru", "Zephyra", "Durnholm",
+];
+
+const GIVEN_SAMPLES: [&str; 22] = [
+    "Leon", "Alina", "Elena", "Eduard", "Alexey", "Michael", "Vasya", "Marina", "Ivan", "Sofia",
+    "Pavel", "Olga", "Nikita", "Anna", "Dmitri", "Irina", "Boris", "Katya", "Roman", "Vera",
+    "Oleg", "Lidia",
+];
+
+const FAMILY_STARTS: [&str; 12] = [
+    "Ash", "Brook", "Cor", "Dun", "Fen", "Gale", "Hol", "Mar", "Or", "Stone", "Thorn", "Wil",
+];
+const FAMILY_MIDDLES: [&str; 6] = ["", "", "a", "en", "i", "o"];
+const FAMILY_ENDS: [&str; 10] = [
+    "by", "croft", "dale", "field", "ford", "ley", "mere", "ton", "well", "wick",
+];
+
+/// Order-2 character chain trained on sample names.
+struct MarkovChain {
+    starts: Vec<String>,
+    transitions: HashMap<String, Vec<Option<char>>>,
+    min_len: usize,
+    max_len: usize,
+}
+
+impl MarkovChain {
+    const ORDER: usize = 2;
+
+    fn train(samples: &[&str], min_len: usize, max_len: usize) -> Self {
+        let mut starts: Vec<String> = Vec::new();
+        let mut transitions: HashMap<String, Vec<Option<char>>> = HashMap::new();
+        for sample in samples {
+            let chars: Vec<char> = sample.to_lowercase().chars().collect();
+            if chars.len() <= Self::ORDER {
+                continue;
+            }
+            starts.push(chars[..Self::ORDER].iter().collect());
+            for i in 0..=chars.len() - Self::ORDER {
+                let key: String = chars[i..i + Self::ORDER].iter().collect();
+                let next = chars.get(i + Self::ORDER).copied();
+                transitions.entry(key).or_default().push(next);
+            }
+        }
+        Self {
+            starts,
+            transitions,
+            min_len,
+            max_len,
+        }
+    }
+
+    fn generate<R: Rng>(&self, rng: &mut R) -> Option<String> {
+        let mut name = self.starts.choose(rng)?.clone();
+        while name.chars().count() < self.max_len {
+            let key: String = name
+                .chars()
+                .skip(name.chars().count() - Self::ORDER)
+                .collect();
+            match self.transitions.get(&key).and_then(|next| next.choose(rng)) {
+                Some(Some(c)) => name.push(*c),
+                _ => break,
+            }
+        }
+        if name.chars().count() < self.min_len {
+            return None;
+        }
+        Some(capitalize(&name))
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Seedable name source for islands and people that never repeats a name within a world.
+pub struct NameGenerator {
+    rng: StdRng,
+    islands: MarkovChain,
+    given: MarkovChain,
+    used: HashSet<String>,
+}
+
+impl NameGenerator {
+    pub fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            rng,
+            islands: MarkovChain::train(&ISLAND_SAMPLES, 4, 9),
+            given: MarkovChain::train(&GIVEN_SAMPLES, 3, 8),
+            used: HashSet::new(),
+        }
+    }
+
+    fn family(&mut self) -> String {
+        let start = FAMILY_STARTS.choose(&mut self.rng).unwrap();
+        let middle = FAMILY_MIDDLES.choose(&mut self.rng).unwrap();
+        let end = FAMILY_ENDS.choose(&mut self.rng).unwrap();
+        format!("{}{}{}", start, middle, end)
+    }
+
+    /// Keeps drawing from `make` until it yields an unused name, falling back to a numbered one.
+    fn unique(&mut self, make: impl Fn(&mut Self) -> Option<String>) -> String {
+        let mut last = None;
+        for _ in 0..MAX_ATTEMPTS {
+            if let Some(name) = make(self) {
+                if self.used.insert(name.clone()) {
+                    return name;
+                }
+                last = Some(name);
+            }
+        }
+        let base = last.unwrap_or_else(|| "Nameless".to_string());
+        let name = (2..)
+            .map(|i| format!("{} {}", base, i))
+            .find(|name| !self.used.contains(name))
+            .unwrap();
+        self.used.insert(name.clone());
+        name
+    }
+
+    pub fn island_name(&mut self) -> String {
+        self.unique(|names| {
+            let rng = &mut names.rng;
+            names.islands.generate(rng)
+        })
+    }
+
+    pub fn family_name(&mut self) -> String {
+        self.unique(|names| Some(names.family()))
+    }
+
+    /// A full name in the given family, unique among all people in the world.
+    pub fn person_name(&mut self, family: &str) -> String {
+        self.unique(|names| {
+            let rng = &mut names.rng;
+            names
+                .given
+                .generate(rng)
+                .map(|given| format!("{} {}", given, family))
+        })
+    }
+}
+
+fn setup_names(commands: &mut Commands, config: Res<GameConfig>) {
+    commands.insert_resource(NameGenerator::new(config.seed));
+}
+
+pub struct NamesPlugin;
+impl Plugin for NamesPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_world_gen_system(MyStages::Seeding, setup_names.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_names() {
+        let mut a = NameGenerator::new(Some(7));
+        let mut b = NameGenerator::new(Some(7));
+        for _ in 0..20 {
+            assert_eq!(a.island_name(), b.island_name());
+            let family = a.family_name();
+            assert_eq!(family, b.family_name());
+            assert_eq!(a.person_name(&family), b.person_name(&family));
+        }
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let mut names = NameGenerator::new(Some(1));
+        let mut seen = HashSet::new();
+        // more families than the syllable tables can make, so the numbered fallback is hit
+        for _ in 0..1000 {
+            assert!(seen.insert(names.family_name()));
+        }
+        for _ in 0..200 {
+            assert!(seen.insert(names.island_name()));
+            assert!(seen.insert(names.person_name("Ashby")));
+        }
+    }
+
+    #[test]
+    fn falls_back_to_numbered_names() {
+        let mut names = NameGenerator::new(Some(1));
+        let same = |_: &mut NameGenerator| Some("Ocor".to_string());
+        assert_eq!(names.unique(same), "Ocor");
+        assert_eq!(names.unique(same), "Ocor 2");
+        assert_eq!(names.unique(same), "Ocor 3");
+        assert_eq!(names.unique(|_| None), "Nameless 2");
+        assert_eq!(names.unique(|_| None), "Nameless 3");
+    }
+
+    #[test]
+    fn generated_names_respect_length_bounds() {
+        let chain = MarkovChain::train(&ISLAND_SAMPLES, 4, 9);
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..200 {
+            if let Some(name) = chain.generate(&mut rng) {
+                let len = name.chars().count();
+                assert!((4..=9).contains(&len), "{}", name);
+                assert!(name.chars().next().unwrap().is_uppercase());
+            }
+        }
+    }
+}
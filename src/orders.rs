@@ -0,0 +1,415 @@
+use std::collections::VecDeque;
+
+use bevy::{math::vec3, prelude::*};
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    building::BuildMode,
+    command::GameCommand,
+    faction::{Factions, Owner},
+    house::House,
+    person::{Person, PersonTask, TargetPosition},
+    resource::NaturalResource,
+    ui::{text_bundle, UiFocus, UiFont},
+    CursorPosition, GameStates, Materials, MyStages, Selected,
+};
+
+const PICK_RADIUS: f32 = 8.;
+const MIN_DRAG: f32 = 5.;
+const SELECTION_ROWS: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    MoveTo(Vec2),
+    Gather(Entity),
+    GoHome,
+}
+
+/// Orders given by the player, oldest first. While it is not empty the person
+/// ignores the automatic wander and gather systems.
+#[derive(Debug, Default)]
+pub struct OrderQueue(pub VecDeque<Order>);
+
+pub struct SelectionBox;
+pub struct OrderPath;
+pub struct SelectionText(pub usize);
+
+pub struct OrderMaterials {
+    pub selection_box: Handle<ColorMaterial>,
+    pub path: Handle<ColorMaterial>,
+}
+
+fn shift_held(keys: &Input<KeyCode>) -> bool {
+    keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift)
+}
+
+fn setup_orders(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let order_materials = OrderMaterials {
+        selection_box: color_materials.add(Color::rgba(1., 1., 1., 0.2).into()),
+        path: color_materials.add(Color::rgba(1., 1., 0., 0.7).into()),
+    };
+
+    commands
+        .spawn(SpriteBundle {
+            material: order_materials.selection_box.clone(),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .with(SelectionBox)
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    bottom: Val::Px(34.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            material: color_materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            for i in 0..SELECTION_ROWS {
+                parent
+                    .spawn(text_bundle(&font, "", 14.))
+                    .with(SelectionText(i));
+            }
+        })
+        .insert_resource(order_materials);
+}
+
+/// Click selects one person, dragging selects everyone in the box; shift adds to the selection.
+fn select_people(
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    ui_focus: Res<UiFocus>,
+    build_mode: Res<BuildMode>,
+    cursor: Res<CursorPosition>,
+    mut selected: ResMut<Selected>,
+    mut drag_start: Local<Option<Vec2>>,
+    people_query: Query<(Entity, &GlobalTransform), With<Person>>,
+    mut box_query: Query<(&mut Transform, &mut Sprite, &mut Visible), With<SelectionBox>>,
+) {
+    // forget people that are gone
+    selected
+        .items
+        .retain(|entity| people_query.get(*entity).is_ok());
+
+    if mouse.just_pressed(MouseButton::Left) && !ui_focus.hovered && build_mode.selected.is_none() {
+        *drag_start = Some(cursor.world);
+    }
+    let start = match *drag_start {
+        Some(start) => start,
+        None => return,
+    };
+    let min = start.min(cursor.world);
+    let max = start.max(cursor.world);
+    let dragging = (max - min).length() > MIN_DRAG;
+
+    for (mut transform, mut sprite, mut visible) in box_query.iter_mut() {
+        visible.is_visible = dragging && mouse.pressed(MouseButton::Left);
+        let center = (min + max) / 2.;
+        transform.translation = vec3(center.x, center.y, 60.);
+        sprite.size = max - min;
+    }
+
+    if !mouse.just_released(MouseButton::Left) {
+        return;
+    }
+    *drag_start = None;
+
+    let picked: Vec<Entity> = if dragging {
+        people_query
+            .iter()
+            .filter(|(_, transform)| {
+                let p = transform.translation.truncate();
+                p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y
+            })
+            .map(|(entity, _)| entity)
+            .collect()
+    } else {
+        people_query
+            .iter()
+            .find(|(_, transform)| {
+                (transform.translation.truncate() - cursor.world).length() <= PICK_RADIUS
+            })
+            .map(|(entity, _)| entity)
+            .into_iter()
+            .collect()
+    };
+
+    if !shift_held(&keys) {
+        selected.items.clear();
+    }
+    for entity in picked {
+        if !selected.items.contains(&entity) {
+            selected.items.push(entity);
+        }
+    }
+}
+
+/// Right click gives the selected people an order, `H` sends them home; shift queues it.
+fn issue_orders(
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    ui_focus: Res<UiFocus>,
+    cursor: Res<CursorPosition>,
+    selected: Res<Selected>,
+    factions: Res<Factions>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+    resources_query: Query<(Entity, &GlobalTransform, &Sprite), With<NaturalResource>>,
+    houses_query: Query<(&GlobalTransform, &Sprite), With<House>>,
+) {
+    let under_cursor = |transform: &GlobalTransform, sprite: &Sprite| {
+        let offset = transform.translation.truncate() - cursor.world;
+        offset.x.abs() <= sprite.size.x / 2. && offset.y.abs() <= sprite.size.y / 2.
+    };
+
+    let order = if keys.just_pressed(KeyCode::H) {
+        Order::GoHome
+    } else if mouse.just_pressed(MouseButton::Right) && !ui_focus.hovered {
+        if let Some((resource, _, _)) = resources_query
+            .iter()
+            .find(|(_, transform, sprite)| under_cursor(*transform, *sprite))
+        {
+            Order::Gather(resource)
+        } else if houses_query
+            .iter()
+            .any(|(transform, sprite)| under_cursor(transform, sprite))
+        {
+            Order::GoHome
+        } else {
+            Order::MoveTo(cursor.world)
+        }
+    } else {
+        return;
+    };
+
+    if selected.items.is_empty() {
+        return;
+    }
+    game_commands.send(GameCommand::Order {
+        faction: factions.player,
+        people: selected.items.clone(),
+        order,
+        queue: shift_held(&keys),
+    });
+}
+
+/// Hands out orders any faction gave; people of other factions ignore them.
+fn handle_order_commands(
+    events: Res<Events<GameCommand>>,
+    mut event_reader: Local<EventReader<GameCommand>>,
+    mut people_query: Query<(&Owner, &mut Person, &mut TargetPosition, &mut OrderQueue)>,
+) {
+    for ev in event_reader.iter(&events) {
+        let (faction, people, order, queue) = match ev {
+            GameCommand::Order {
+                faction,
+                people,
+                order,
+                queue,
+            } => (*faction, people, *order, *queue),
+            _ => continue,
+        };
+        for entity in people.iter() {
+            if let Ok((owner, mut person, mut target, mut orders)) = people_query.get_mut(*entity) {
+                if owner.0 != Some(faction) || matches!(person.task, PersonTask::Sailing(_)) {
+                    continue;
+                }
+                if !queue {
+                    // drop whatever they were doing, `advance_orders` starts the new order
+                    orders.0.clear();
+                    person.task = PersonTask::Idle;
+                    target.0 = None;
+                }
+                orders.0.push_back(order);
+            }
+        }
+    }
+}
+
+fn order_position(
+    order: Order,
+    person: &Person,
+    resources_query: &Query<(&GlobalTransform, &NaturalResource)>,
+    houses_query: &Query<&GlobalTransform, With<House>>,
+) -> Option<Vec2> {
+    match order {
+        Order::MoveTo(position) => Some(position),
+        Order::Gather(resource) => resources_query
+            .get(resource)
+            .ok()
+            .map(|(transform, _)| transform.translation.truncate()),
+        Order::GoHome => person
+            .house
+            .and_then(|house| houses_query.get(house).ok())
+            .map(|transform| transform.translation.truncate()),
+    }
+}
+
+/// Starts the next queued order for everyone who finished their previous task.
+fn advance_orders(
+    resources_query: Query<(&GlobalTransform, &NaturalResource)>,
+    houses_query: Query<&GlobalTransform, With<House>>,
+    mut people_query: Query<(&mut Person, &mut TargetPosition, &mut OrderQueue)>,
+) {
+    for (mut person, mut target, mut orders) in people_query.iter_mut() {
+        if person.task != PersonTask::Idle {
+            continue;
+        }
+        while let Some(order) = orders.0.pop_front() {
+            // orders whose target is gone are skipped
+            let position = match order_position(order, &person, &resources_query, &houses_query) {
+                Some(position) => position,
+                None => continue,
+            };
+            person.task = match order {
+                Order::MoveTo(_) => PersonTask::Moving,
+                Order::Gather(resource) => {
+                    let (_, nr) = resources_query.get(resource).unwrap();
+                    PersonTask::Gathering(nr.0, resource)
+                }
+                Order::GoHome => PersonTask::GoingHome,
+            };
+            target.0 = Some(position);
+            break;
+        }
+    }
+}
+
+fn highlight_selected(
+    selected: Res<Selected>,
+    materials: Res<Materials>,
+    mut people_query: Query<(Entity, &mut Handle<ColorMaterial>), With<Person>>,
+) {
+    for (entity, mut material) in people_query.iter_mut() {
+        let wanted = if selected.items.contains(&entity) {
+            &materials.selected_skin
+        } else {
+            &materials.skin
+        };
+        if *material != *wanted {
+            *material = wanted.clone();
+        }
+    }
+}
+
+/// Draws a line from each selected person through their current target and queued orders.
+fn draw_order_paths(
+    commands: &mut Commands,
+    selected: Res<Selected>,
+    order_materials: Res<OrderMaterials>,
+    paths_query: Query<Entity, With<OrderPath>>,
+    resources_query: Query<(&GlobalTransform, &NaturalResource)>,
+    houses_query: Query<&GlobalTransform, With<House>>,
+    people_query: Query<(&Person, &GlobalTransform, &TargetPosition, &OrderQueue)>,
+) {
+    for path in paths_query.iter() {
+        commands.despawn(path);
+    }
+    for entity in selected.items.iter() {
+        let (person, transform, target, orders) = match people_query.get(*entity) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        let mut points = vec![transform.translation.truncate()];
+        points.extend(target.0);
+        points.extend(
+            orders.0.iter().filter_map(|order| {
+                order_position(*order, person, &resources_query, &houses_query)
+            }),
+        );
+        if points.len() < 2 {
+            continue;
+        }
+        commands
+            .spawn(GeometryBuilder::build_as(
+                &shapes::Polygon {
+                    points,
+                    closed: false,
+                },
+                order_materials.path.clone(),
+                TessellationMode::Stroke(StrokeOptions::default().with_line_width(1.5)),
+                Transform::from_translation(vec3(0., 0., 55.)),
+            ))
+            .with(OrderPath);
+    }
+}
+
+pub fn describe_task(task: &PersonTask, target: &TargetPosition) -> String {
+    let target = match target.0 {
+        Some(p) => format!(" to ({:.0}, {:.0})", p.x, p.y),
+        None => String::new(),
+    };
+    match task {
+        PersonTask::Idle => "idle".to_string(),
+        PersonTask::Wandering => format!("wandering{}", target),
+        PersonTask::Gathering(nr_type, _) => format!("gathering {:?}{}", nr_type, target),
+        PersonTask::Moving => format!("moving{}", target),
+        PersonTask::GoingHome => format!("going home{}", target),
+        PersonTask::Sailing(_) => "at sea".to_string(),
+        PersonTask::Working(_) => format!("working{}", target),
+        PersonTask::Sleeping => "asleep".to_string(),
+    }
+}
+
+fn update_selection_text(
+    selected: Res<Selected>,
+    people_query: Query<(&Person, &TargetPosition, &OrderQueue)>,
+    mut text_query: Query<(&mut Text, &SelectionText)>,
+) {
+    let mut lines: Vec<String> = selected
+        .items
+        .iter()
+        .filter_map(|entity| people_query.get(*entity).ok())
+        .map(|(person, target, orders)| {
+            let queued = if orders.0.is_empty() {
+                String::new()
+            } else {
+                format!(", {} queued", orders.0.len())
+            };
+            format!(
+                "{}: {}{}",
+                person.name,
+                describe_task(&person.task, target),
+                queued
+            )
+        })
+        .collect();
+    if lines.len() > SELECTION_ROWS {
+        let hidden = lines.len() - (SELECTION_ROWS - 1);
+        lines.truncate(SELECTION_ROWS - 1);
+        lines.push(format!("and {} more", hidden));
+    }
+
+    for (mut text, row) in text_query.iter_mut() {
+        let value = lines.get(row.0).cloned().unwrap_or_default();
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub struct OrdersPlugin;
+impl Plugin for OrdersPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_world_gen_system(MyStages::Ui, setup_orders.system())
+            .add_game_system(select_people.system())
+            .add_game_system(issue_orders.system())
+            .add_game_system(handle_order_commands.system())
+            .add_game_system(advance_orders.system())
+            .add_game_system(highlight_selected.system())
+            .add_game_system(draw_order_paths.system())
+            .add_game_system(update_selection_text.system());
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+
+use bevy::{math::vec3, prelude::*};
+
+use crate::{
+    feed::GameEvent,
+    person::Person,
+    resource::{NaturalResource, NaturalResourceType},
+    ui::{text_bundle, UiFont},
+    GameStates, GameTime, MyStages,
+};
+
+const CELL_SIZE: f32 = 100.;
+/// Half the side of the overlaid square, in world units.
+const OVERLAY_HALF_EXTENT: f32 = 1200.;
+const CELLS_PER_SIDE: usize = (2. * OVERLAY_HALF_EXTENT / CELL_SIZE) as usize;
+const REFRESH_INTERVAL: f32 = 0.5;
+/// How far back the gather activity overlay looks, in seconds.
+const ACTIVITY_WINDOW: f64 = 3. * 60.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverlayMode {
+    Off,
+    Resource(NaturalResourceType),
+    Population,
+    GatherActivity,
+}
+
+impl Default for OverlayMode {
+    fn default() -> Self {
+        OverlayMode::Off
+    }
+}
+
+impl OverlayMode {
+    /// Off, then every resource type, then population and gather activity.
+    pub fn next(self) -> Self {
+        let all = NaturalResourceType::ALL;
+        match self {
+            OverlayMode::Off => OverlayMode::Resource(all[0]),
+            OverlayMode::Resource(nr_type) => {
+                match all
+                    .iter()
+                    .position(|t| *t == nr_type)
+                    .and_then(|i| all.get(i + 1))
+                {
+                    Some(next) => OverlayMode::Resource(*next),
+                    None => OverlayMode::Population,
+                }
+            }
+            OverlayMode::Population => OverlayMode::GatherActivity,
+            OverlayMode::GatherActivity => OverlayMode::Off,
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            OverlayMode::Off => "Overlay: off [O]".to_string(),
+            OverlayMode::Resource(nr_type) => format!("Overlay: {:?} density [O]", nr_type),
+            OverlayMode::Population => "Overlay: population density [O]".to_string(),
+            OverlayMode::GatherActivity => format!(
+                "Overlay: gathering in the last {} min [O]",
+                ACTIVITY_WINDOW / 60.
+            ),
+        }
+    }
+}
+
+pub struct OverlayCell(pub usize);
+pub struct OverlayText;
+
+pub struct OverlayState {
+    pub mode: OverlayMode,
+    timer: Timer,
+    /// Set when the mode changes, so the overlay updates without waiting for the timer.
+    dirty: bool,
+    /// When and where recent gathers happened, oldest first.
+    gathers: VecDeque<(f64, Vec2)>,
+}
+
+impl Default for OverlayState {
+    fn default() -> Self {
+        Self {
+            mode: OverlayMode::Off,
+            timer: Timer::from_seconds(REFRESH_INTERVAL, true),
+            dirty: false,
+            gathers: VecDeque::new(),
+        }
+    }
+}
+
+fn cell_index(position: Vec2) -> Option<usize> {
+    let x = ((position.x + OVERLAY_HALF_EXTENT) / CELL_SIZE).floor();
+    let y = ((position.y + OVERLAY_HALF_EXTENT) / CELL_SIZE).floor();
+    let side = CELLS_PER_SIDE as f32;
+    if x < 0. || y < 0. || x >= side || y >= side {
+        return None;
+    }
+    Some(y as usize * CELLS_PER_SIDE + x as usize)
+}
+
+fn heat_color(value: f32) -> Color {
+    if value <= 0. {
+        return Color::NONE;
+    }
+    Color::rgba(1., 1. - value, 0., 0.15 + 0.45 * value)
+}
+
+fn setup_overlay(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for i in 0..CELLS_PER_SIDE * CELLS_PER_SIDE {
+        let x = (i % CELLS_PER_SIDE) as f32 * CELL_SIZE - OVERLAY_HALF_EXTENT + CELL_SIZE / 2.;
+        let y = (i / CELLS_PER_SIDE) as f32 * CELL_SIZE - OVERLAY_HALF_EXTENT + CELL_SIZE / 2.;
+        commands
+            .spawn(SpriteBundle {
+                material: materials.add(Color::NONE.into()),
+                sprite: Sprite::new(Vec2::splat(CELL_SIZE)),
+                transform: Transform::from_translation(vec3(x, y, 1.)),
+                visible: Visible {
+                    is_visible: false,
+                    is_transparent: true,
+                },
+                ..Default::default()
+            })
+            .with(OverlayCell(i));
+    }
+
+    let mut text = text_bundle(&font, "", 16.);
+    text.style.position_type = PositionType::Absolute;
+    text.style.position = Rect {
+        left: Val::Px(300.),
+        top: Val::Px(10.),
+        ..Default::default()
+    };
+    text.visible.is_visible = false;
+    commands.spawn(text).with(OverlayText);
+}
+
+fn record_gathers(
+    time: Res<GameTime>,
+    events: Res<Events<GameEvent>>,
+    mut event_reader: Local<EventReader<GameEvent>>,
+    mut state: ResMut<OverlayState>,
+) {
+    let now = time.seconds_since_startup();
+    for ev in event_reader.iter(&events) {
+        if let GameEvent::ResourceGathered { position, .. } = ev {
+            state.gathers.push_back((now, *position));
+        }
+    }
+    while let Some((at, _)) = state.gathers.front() {
+        if now - *at <= ACTIVITY_WINDOW {
+            break;
+        }
+        state.gathers.pop_front();
+    }
+}
+
+fn toggle_overlay(keys: Res<Input<KeyCode>>, mut state: ResMut<OverlayState>) {
+    if keys.just_pressed(KeyCode::O) {
+        state.mode = state.mode.next();
+        state.dirty = true;
+    }
+}
+
+fn update_overlay_text(
+    state: Res<OverlayState>,
+    mut text_query: Query<(&mut Text, &mut Visible), With<OverlayText>>,
+) {
+    let value = state.mode.label();
+    let shown = state.mode != OverlayMode::Off;
+    for (mut text, mut visible) in text_query.iter_mut() {
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+fn refresh_overlay(
+    time: Res<GameTime>,
+    mut state: ResMut<OverlayState>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    resources_query: Query<(&GlobalTransform, &NaturalResource)>,
+    people_query: Query<&GlobalTransform, With<Person>>,
+    mut cells_query: Query<(&OverlayCell, &Handle<ColorMaterial>, &mut Visible)>,
+) {
+    let ticked = state.timer.tick(time.delta_seconds()).just_finished();
+    if !ticked && !state.dirty {
+        return;
+    }
+    state.dirty = false;
+    let mode = state.mode;
+
+    let mut counts = vec![0u32; CELLS_PER_SIDE * CELLS_PER_SIDE];
+    let positions: Vec<Vec2> = match mode {
+        OverlayMode::Off => Vec::new(),
+        OverlayMode::Resource(nr_type) => resources_query
+            .iter()
+            .filter(|(_, nr)| nr.0 == nr_type)
+            .map(|(transform, _)| transform.translation.truncate())
+            .collect(),
+        OverlayMode::Population => people_query
+            .iter()
+            .map(|transform| transform.translation.truncate())
+            .collect(),
+        OverlayMode::GatherActivity => state.gathers.iter().map(|(_, p)| *p).collect(),
+    };
+    for position in positions {
+        if let Some(i) = cell_index(position) {
+            counts[i] += 1;
+        }
+    }
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+
+    for (cell, material, mut visible) in cells_query.iter_mut() {
+        let shown = mode != OverlayMode::Off && counts[cell.0] > 0;
+        if visible.is_visible != shown {
+            visible.is_visible = shown;
+        }
+        if shown {
+            if let Some(material) = materials.get_mut(material) {
+                material.color = heat_color(counts[cell.0] as f32 / max);
+            }
+        }
+    }
+}
+
+pub struct OverlayPlugin;
+impl Plugin for OverlayPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<OverlayState>()
+            .add_world_gen_system(MyStages::Ui, setup_overlay.system())
+            .add_game_system(record_gathers.system())
+            .add_game_system(toggle_overlay.system())
+            .add_game_system(update_overlay_text.system())
+            .add_game_system(refresh_overlay.system());
+    }
+}
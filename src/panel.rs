@@ -0,0 +1,262 @@
+use bevy::prelude::*;
+
+use crate::{
+    boat::{Boat, Dock},
+    building::BuildMode,
+    faction::{Factions, Owner},
+    fog::{FogOfWar, Sight},
+    house::House,
+    island::{Biome, Island, IslandGoods, IslandNR, Title},
+    market::{sparkline, Market, MarketSelection, CURRENCY, SELL_RATE},
+    person::Person,
+    resource::NaturalResourceType,
+    stats::{count_houses, count_population, IslandHistory},
+    ui::{text_bundle, UiFocus, UiFont},
+    workshop::{Good, Workshop},
+    CursorPosition, GameStates, Materials, MyStages,
+};
+
+pub const PANEL_WIDTH: f32 = 280.;
+pub const PANEL_LINES: usize = 17;
+const PANEL_LINE_HEIGHT: f32 = 18.;
+pub const PANEL_HEIGHT: f32 = PANEL_LINES as f32 * PANEL_LINE_HEIGHT + 8.;
+
+#[derive(Debug, Default)]
+pub struct SelectedIsland(pub Option<Entity>);
+
+/// Root node and every text line of the island panel, so they can be shown and hidden together.
+pub struct IslandPanelPart;
+pub struct IslandPanelLine(pub usize);
+
+pub fn setup_island_panel(commands: &mut Commands, font: Res<UiFont>, materials: Res<Materials>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(PANEL_WIDTH), Val::Px(PANEL_HEIGHT)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    top: Val::Px(10.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: Rect::all(Val::Px(4.)),
+                ..Default::default()
+            },
+            material: materials.transparent.clone(),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .with(IslandPanelPart)
+        .with(Interaction::default())
+        .with_children(|parent| {
+            for i in 0..PANEL_LINES {
+                let mut line = text_bundle(&font, "", 14.);
+                line.style.size = Size::new(Val::Auto, Val::Px(PANEL_LINE_HEIGHT));
+                line.visible.is_visible = false;
+                parent
+                    .spawn(line)
+                    .with(IslandPanelPart)
+                    .with(IslandPanelLine(i));
+            }
+        });
+}
+
+fn select_island(
+    mouse: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    ui_focus: Res<UiFocus>,
+    build_mode: Res<BuildMode>,
+    cursor: Res<CursorPosition>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    mut selected: ResMut<SelectedIsland>,
+    islands_query: Query<(Entity, &Transform, &crate::island::Size), With<Island>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        selected.0 = None;
+        return;
+    }
+    if !mouse.just_pressed(MouseButton::Left) || ui_focus.hovered || build_mode.selected.is_some() {
+        return;
+    }
+    selected.0 = islands_query
+        .iter()
+        .find(|(entity, transform, size)| {
+            let center = Vec2::new(transform.translation.x, transform.translation.y);
+            // undiscovered islands are still under the fog
+            fog.knows_island(factions.player, *entity)
+                && (cursor.world - center).length() <= size.width / 2.
+        })
+        .map(|(entity, _, _)| entity);
+}
+
+fn update_island_panel(
+    selected: Res<SelectedIsland>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    market_selection: Res<MarketSelection>,
+    islands_query: Query<(
+        &GlobalTransform,
+        &Title,
+        &Biome,
+        &Owner,
+        &IslandNR,
+        &IslandGoods,
+        Option<&IslandHistory>,
+        Option<&Market>,
+    )>,
+    houses_query: Query<&House>,
+    people_query: Query<&Person>,
+    docks_query: Query<(Entity, &Dock)>,
+    boats_query: Query<&Boat>,
+    workshops_query: Query<&Workshop>,
+    mut visible_query: Query<&mut Visible, With<IslandPanelPart>>,
+    mut lines_query: Query<(&mut Text, &IslandPanelLine)>,
+) {
+    let island = selected
+        .0
+        .and_then(|island| islands_query.get(island).ok().map(|found| (island, found)));
+
+    for mut visible in visible_query.iter_mut() {
+        if visible.is_visible != island.is_some() {
+            visible.is_visible = island.is_some();
+        }
+    }
+
+    let (island_entity, (transform, title, biome, owner, island_nr, goods, history, market)) =
+        match island {
+            Some(island) => island,
+            None => return,
+        };
+
+    // other factions' islands are only known as of the last time someone looked
+    let live = owner.0 == Some(factions.player)
+        || fog.sight(factions.player, transform.translation.truncate()) == Sight::Watched;
+    if !live {
+        let last_seen = fog
+            .map(factions.player)
+            .and_then(|map| map.last_seen(island_entity));
+        let mut lines = vec![
+            format!("{} ({:?}), last seen", title.0, biome),
+            format!("Owner: {}", factions.name_of(*owner)),
+        ];
+        for nr_type in NaturalResourceType::ALL.iter() {
+            let count = last_seen
+                .and_then(|stock| stock.get(nr_type))
+                .copied()
+                .unwrap_or(0);
+            lines.push(format!("{:?}: {}", nr_type, count));
+        }
+        lines.push("[Esc] close".to_string());
+        for (mut text, line) in lines_query.iter_mut() {
+            let value = lines.get(line.0).cloned().unwrap_or_default();
+            if text.value != value {
+                text.value = value;
+            }
+        }
+        return;
+    }
+
+    let gathers_per_minute = history
+        .and_then(|history| history.samples.back())
+        .map_or(0., |sample| sample.gathers_per_minute);
+
+    let mut lines = vec![
+        format!("{} ({:?})", title.0, biome),
+        format!("Owner: {}", factions.name_of(*owner)),
+        format!(
+            "Population: {}",
+            count_population(island_entity, &houses_query, &people_query)
+        ),
+        format!(
+            "Houses: {}, room for {}",
+            count_houses(island_entity, &houses_query),
+            houses_query
+                .iter()
+                .filter(|house| house.island == island_entity)
+                .map(|house| house.capacity())
+                .sum::<usize>()
+        ),
+        format!("Gathers/min: {:.1}", gathers_per_minute),
+    ];
+    let docks: Vec<Entity> = docks_query
+        .iter()
+        .filter(|(_, dock)| dock.island == island_entity)
+        .map(|(entity, _)| entity)
+        .collect();
+    let boats = boats_query
+        .iter()
+        .filter(|boat| docks.contains(&boat.dock))
+        .count();
+    lines.push(format!(
+        "Docks: {}, boats: {} [N] build boat",
+        docks.len(),
+        boats
+    ));
+    for nr_type in NaturalResourceType::ALL.iter() {
+        let count = island_nr.0.get(nr_type).copied().unwrap_or(0);
+        match market {
+            Some(market) if *nr_type != CURRENCY => lines.push(format!(
+                "{:?}: {} ({:.2}g)",
+                nr_type,
+                count,
+                market.price(*nr_type)
+            )),
+            _ => lines.push(format!("{:?}: {}", nr_type, count)),
+        }
+    }
+    lines.push(format!(
+        "Goods: {}",
+        Good::ALL
+            .iter()
+            .map(|good| format!("{:?} {}", good, goods.0.get(good).copied().unwrap_or(0)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    let workshops: Vec<String> = workshops_query
+        .iter()
+        .filter(|workshop| workshop.island == island_entity)
+        .map(Workshop::describe)
+        .collect();
+    lines.push(match workshops.len() {
+        0 => "No workshops".to_string(),
+        1 => workshops[0].clone(),
+        n => format!("{}, and {} more", workshops[0], n - 1),
+    });
+    if let Some(market) = market {
+        let traded = market_selection.0;
+        lines.push(format!(
+            "[M] {:?}: buy {:.2}g, sell {:.2}g [+/-]",
+            traded,
+            market.price(traded),
+            market.price(traded) * SELL_RATE
+        ));
+        lines.push(format!(
+            "Price: {}",
+            sparkline(market.price_history(traded))
+        ));
+    }
+    lines.push("Chart: stock, people, houses, gathers".to_string());
+    lines.push("[E] export stats, [Esc] close".to_string());
+
+    for (mut text, line) in lines_query.iter_mut() {
+        let value = lines.get(line.0).cloned().unwrap_or_default();
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub struct IslandPanelPlugin;
+impl Plugin for IslandPanelPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<SelectedIsland>()
+            .add_world_gen_system(MyStages::Ui, setup_island_panel.system())
+            .add_game_system(select_island.system())
+            .add_game_system(update_island_panel.system());
+    }
+}
@@ -9,23 +9,36 @@ use bevy::{
     prelude::*,
     tasks::ParallelIterator,
 };
-use rand::{prelude::SliceRandom, Rng};
+use rand::Rng;
 
 pub struct GatherEvent(pub Entity, pub NaturalResourceType);
 
 use crate::{
+    clock::{DayPhase, WorldClock},
+    config::{ConfigChanged, GameConfig},
     house::House,
+    names::NameGenerator,
+    orders::OrderQueue,
     resource::{NaturalResource, NaturalResourceType},
-    Materials, MyStages,
+    road::{RoadNetwork, Route, ROAD_SPEED_FACTOR},
+    weather::Weather,
+    GameStates, GameTime, Materials, MyStages, WorldRng,
 };
 
-const PERSON_SPEED: f32 = 200.;
-
 #[derive(Debug, PartialEq, Eq)]
 pub enum PersonTask {
     Idle,
     Gathering(NaturalResourceType, Entity),
     Wandering,
+    /// Walking somewhere the player ordered.
+    Moving,
+    GoingHome,
+    /// On board a boat, hidden until it lands.
+    Sailing(Entity),
+    /// Staffing a workshop until ordered elsewhere.
+    Working(Entity),
+    /// In bed for the night, hidden until morning.
+    Sleeping,
 }
 
 impl Default for PersonTask {
@@ -55,50 +68,88 @@ impl Default for Person {
     }
 }
 
-pub const AVAILABLE_PERSON_NAMES: [&str; 7] = [
-    "Leon", "Alina", "Elena", "Eduard", "Alexey", "Michael", "Vasya",
-];
+pub fn spawn_person(
+    commands: &mut Commands,
+    materials: &Materials,
+    name: String,
+    house: Option<Entity>,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            material: materials.skin.clone(),
+            sprite: Sprite::new(Vec2::new(5.0, 11.0)),
+            transform: Transform::from_translation(vec3(position.x, position.y, 10.)),
+            ..Default::default()
+        })
+        .with(Person {
+            name,
+            house,
+            ..Default::default()
+        })
+        .with(TargetPosition(None))
+        .with(Route::default())
+        .with(OrderQueue::default())
+        .current_entity()
+        .unwrap()
+}
 
 pub fn colonize_homes(
     commands: &mut Commands,
     materials: Res<Materials>,
-    query: Query<(&Transform, Entity), With<House>>,
+    mut names: ResMut<NameGenerator>,
+    query: Query<(&Transform, &House, Entity)>,
 ) {
-    let mut rng = rand::thread_rng();
-    for (house_transform, house_entity) in query.iter() {
+    for (house_transform, house, house_entity) in query.iter() {
         println!("Spawning person");
-        commands
-            .spawn(SpriteBundle {
-                material: materials.skin.clone(),
-                sprite: Sprite::new(Vec2::new(5.0, 11.0)),
-                transform: Transform::from_translation(vec3(
-                    house_transform.translation.x,
-                    house_transform.translation.y,
-                    10.,
-                )),
-                ..Default::default()
-            })
-            .with(Person {
-                name: AVAILABLE_PERSON_NAMES.choose(&mut rng).unwrap().to_string(),
-                house: Some(house_entity),
-                ..Default::default()
-            })
-            .with(TargetPosition(None));
+        spawn_person(
+            commands,
+            &materials,
+            names.person_name(&house.family),
+            Some(house_entity),
+            vec2(house_transform.translation.x, house_transform.translation.y),
+        );
     }
 }
 
 pub fn move_people(
     commands: &mut Commands,
-    time: Res<Time>,
+    time: Res<GameTime>,
+    config: Res<GameConfig>,
     mut gather_event: ResMut<Events<GatherEvent>>,
-    mut query: Query<(&mut Transform, &mut TargetPosition, &mut Person, Entity)>,
+    weather: Res<Weather>,
+    roads_query: Query<&RoadNetwork>,
+    mut query: Query<(
+        &mut Transform,
+        &mut TargetPosition,
+        &mut Route,
+        &mut Person,
+        Entity,
+    )>,
 ) {
     // For every person
-    let delta = time.delta_seconds() * PERSON_SPEED;
-    for (mut trans, mut optional_target, mut person, person_entity) in query.iter_mut() {
+    let delta = time.delta_seconds() * config.person_speed * weather.current.speed_factor();
+    for (mut trans, mut optional_target, mut route, mut person, person_entity) in query.iter_mut() {
         // println!("Moving pal named: {:?}", person.1.name.clone());
         // If there is a place they want to go
         if let Some(target) = optional_target.0 {
+            // junctions on the way come first
+            if let Some(junction) = route.0.front().copied() {
+                let offset = junction - trans.translation.truncate();
+                if offset.length_squared() < 4. {
+                    route.0.pop_front();
+                    continue;
+                }
+            }
+            let target = route.0.front().copied().unwrap_or(target);
+            let on_road = roads_query
+                .iter()
+                .any(|network| network.is_on_road(trans.translation.truncate()));
+            let delta = if on_road {
+                delta * ROAD_SPEED_FACTOR
+            } else {
+                delta
+            };
             // MAKE THE NORMALIZED DIR VEC
             let dir = vec3(
                 target.x - trans.translation.x,
@@ -120,42 +171,27 @@ pub fn move_people(
                     _ => {}
                 };
 
-                person.task = PersonTask::Idle;
+                // workers stay on the job once they get there
+                if !matches!(person.task, PersonTask::Working(_)) {
+                    person.task = PersonTask::Idle;
+                }
 
                 continue;
             }
-            let dir = delta * dir.normalize();
+            let dir = dir.normalize() * delta.min(dir.length());
 
             trans.translation += dir;
         }
     }
 }
 
-fn handle_gather_events(
-    events: Res<Events<GatherEvent>>,
-    mut event_reader: Local<EventReader<GatherEvent>>,
-    mut houses_query: Query<&mut Sprite, With<House>>,
-    mut people_query: Query<&Person>,
-) {
-    for ev in event_reader.iter(&events) {
-        let maybe_person = people_query.get(ev.0);
-        if let Ok(person) = maybe_person {
-            let maybe_house = person.house;
-            if let Some(house) = maybe_house {
-                let maybe_sprite = houses_query.get_mut(house);
-                if let Ok(mut sprite) = maybe_sprite {
-                    println!("Enlarging {}'s house!", person.name);
-                    sprite.size.y += 10.;
-                }
-            }
-        }
-    }
-}
-
 pub fn make_people_wander(
-    time: Res<Time>,
+    time: Res<GameTime>,
+    config: Res<GameConfig>,
+    clock: Res<WorldClock>,
+    mut world_rng: ResMut<WorldRng>,
     mut timer: ResMut<WanderTimer>,
-    mut query: Query<(&mut TargetPosition, &mut Person)>,
+    mut query: Query<(&mut TargetPosition, &mut Person, &OrderQueue, &Transform)>,
     query_houses: Query<&GlobalTransform, With<House>>,
 ) {
     // update our timer with the time elapsed since the last update
@@ -163,16 +199,21 @@ pub fn make_people_wander(
     if !timer.0.tick(time.delta_seconds()).just_finished() {
         return;
     }
+    // people only stroll around in the evening
+    if clock.phase() != DayPhase::Evening {
+        return;
+    }
 
-    let mut rng = rand::thread_rng();
+    let rng = &mut world_rng.0;
 
-    for (mut target, mut person) in query.iter_mut() {
+    for (mut target, mut person, orders, transform) in query.iter_mut() {
         match person.task {
-            PersonTask::Idle => {}
+            PersonTask::Idle if orders.0.is_empty() => {}
             _ => continue,
         }
         person.task = PersonTask::Wandering;
-        let mut anchor: (f32, f32) = (0., 0.);
+        // the homeless wander around where they are
+        let mut anchor: (f32, f32) = (transform.translation.x, transform.translation.y);
         // let home = person.house;
         if let Some(home) = person.house {
             if let Ok(aaa) = query_houses.get(home) {
@@ -183,8 +224,8 @@ pub fn make_people_wander(
                 anchor = (aaa.translation.x, aaa.translation.y);
             }
         }
-        let x: f32 = anchor.0 + rng.gen_range(-100.0..=100.0);
-        let y: f32 = anchor.1 + rng.gen_range(-100.0..=100.0);
+        let x: f32 = anchor.0 + rng.gen_range(-config.wander_radius..=config.wander_radius);
+        let y: f32 = anchor.1 + rng.gen_range(-config.wander_radius..=config.wander_radius);
         // rng.gen_range(0..1);
         // println!("{} wants to go to {};{}!", person.1.name, x, y);
         *target = TargetPosition(Some(vec2(x, y)))
@@ -192,20 +233,27 @@ pub fn make_people_wander(
 }
 
 pub fn make_people_gather(
-    time: Res<Time>,
+    time: Res<GameTime>,
+    clock: Res<WorldClock>,
+    mut world_rng: ResMut<WorldRng>,
     mut timer: ResMut<GatherTimer>,
-    mut person_q: Query<(&Transform, &mut Person, &mut TargetPosition)>,
+    mut person_q: Query<(&Transform, &mut Person, &mut TargetPosition, &OrderQueue)>,
     nr_q: Query<(&Transform, &NaturalResource, Entity)>,
     pool: Res<bevy::tasks::ComputeTaskPool>,
 ) {
     if !timer.0.tick(time.delta_seconds()).just_finished() {
         return;
     }
+    if clock.phase() != DayPhase::Day {
+        return;
+    }
 
-    let mut rng = rand::thread_rng();
+    let rng = &mut world_rng.0;
 
-    for (person_t, mut per, mut tar) in person_q.iter_mut() {
-        if !(per.task == PersonTask::Idle || per.task == PersonTask::Wandering) {
+    for (person_t, mut per, mut tar, orders) in person_q.iter_mut() {
+        if !(per.task == PersonTask::Idle || per.task == PersonTask::Wandering)
+            || !orders.0.is_empty()
+        {
             continue;
         }
         let person_tr_v2 = vec2(person_t.translation.x, person_t.translation.y);
@@ -256,18 +304,43 @@ pub fn make_people_gather(
     }
 }
 
+fn setup_timers(commands: &mut Commands, config: Res<GameConfig>) {
+    // the reason we call from_seconds with the true flag is to make the timer repeat itself
+    commands
+        .insert_resource(WanderTimer(Timer::from_seconds(
+            config.wander_interval,
+            true,
+        )))
+        .insert_resource(GatherTimer(Timer::from_seconds(
+            config.gather_interval,
+            true,
+        )));
+}
+
+fn apply_config_timers(
+    config: Res<GameConfig>,
+    events: Res<Events<ConfigChanged>>,
+    mut event_reader: Local<EventReader<ConfigChanged>>,
+    mut wander_timer: ResMut<WanderTimer>,
+    mut gather_timer: ResMut<GatherTimer>,
+) {
+    if event_reader.iter(&events).next().is_none() {
+        return;
+    }
+    wander_timer.0 = Timer::from_seconds(config.wander_interval, true);
+    gather_timer.0 = Timer::from_seconds(config.gather_interval, true);
+}
+
 pub struct PeoplePlugin;
 impl Plugin for PeoplePlugin {
     fn build(&self, app: &mut AppBuilder) {
-        // the reason we call from_seconds with the true flag is to make the timer repeat itself
-        app.add_resource(WanderTimer(Timer::from_seconds(2.0, true)))
-            .add_resource(GatherTimer(Timer::from_seconds(1.0, true)))
+        app.add_startup_system(setup_timers.system())
             .add_event::<GatherEvent>()
             // .add_startup_system(add_people.system())
-            .add_startup_system_to_stage(MyStages::People.to_str(), colonize_homes.system())
-            .add_system(make_people_wander.system())
-            .add_system(move_people.system())
-            .add_system(make_people_gather.system())
-            .add_system(handle_gather_events.system());
+            .add_world_gen_system(MyStages::People, colonize_homes.system())
+            .add_game_system(make_people_wander.system())
+            .add_game_system(move_people.system())
+            .add_game_system(make_people_gather.system())
+            .add_game_system(apply_config_timers.system());
     }
 }
@@ -5,9 +5,27 @@ use bevy::{
     math::{vec2, vec3},
     prelude::*,
 };
-use rand::Rng;
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+
+use crate::{
+    config::GameConfig,
+    island::{Biome, Island, Size},
+    weather::Weather,
+    GameStates, GameTime, MyStages, WorldRng,
+};
 
-use crate::MyStages;
+/// Seconds between groves and springs growing back.
+const REGROWTH_INTERVAL: f32 = 10.;
+/// Chance per interval, in fair weather, that a Wood or Water node seeds another.
+const REGROWTH_CHANCE: f64 = 0.05;
+const REGROWTH_SPREAD: f32 = 12.;
+/// Regrowth stops once a resource has this many nodes.
+const MAX_REGROWN_NODES: usize = 400;
+
+pub struct RegrowthTimer(Timer);
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum NaturalResourceType {
@@ -19,24 +37,25 @@ pub enum NaturalResourceType {
 }
 
 impl NaturalResourceType {
-    pub fn choose<R>(rng: &mut R) -> Self
-    where
-        R: Rng + ?Sized,
-    {
-        let i: usize = rng.gen_range(0..5);
-        match i {
-            0 => Self::Coal,
-            1 => Self::Iron,
-            2 => Self::Gold,
-            3 => Self::Wood,
-            4 => Self::Wood,
-            _ => {
-                panic!("Unexpected random resource");
-            }
+    pub const ALL: [Self; 5] = [Self::Coal, Self::Iron, Self::Gold, Self::Wood, Self::Water];
+
+    /// Wood and Water grow back, the rest are ores.
+    pub fn is_ore(self) -> bool {
+        !matches!(self, Self::Wood | Self::Water)
+    }
+
+    /// How many nodes a grove or vein of this type has, and how far they spread.
+    pub fn cluster_shape(self) -> (std::ops::Range<usize>, f32) {
+        match self {
+            Self::Wood => (4..9, 25.),
+            Self::Water => (2..5, 12.),
+            Self::Coal | Self::Iron => (3..6, 15.),
+            Self::Gold => (2..4, 8.),
         }
     }
 }
 
+#[derive(Default)]
 pub struct NaturalResourceMaterials {
     pub coal: Handle<ColorMaterial>,
     pub iron: Handle<ColorMaterial>,
@@ -45,6 +64,18 @@ pub struct NaturalResourceMaterials {
     pub water: Handle<ColorMaterial>,
 }
 
+impl NaturalResourceMaterials {
+    pub fn get(&self, nr_type: NaturalResourceType) -> Handle<ColorMaterial> {
+        match nr_type {
+            NaturalResourceType::Coal => self.coal.clone(),
+            NaturalResourceType::Iron => self.iron.clone(),
+            NaturalResourceType::Gold => self.gold.clone(),
+            NaturalResourceType::Wood => self.wood.clone(),
+            NaturalResourceType::Water => self.water.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct NaturalResource(pub NaturalResourceType);
 // pub struct
@@ -63,32 +94,117 @@ pub fn make_resource_materials(
     });
 }
 
-pub fn plant_resources(commands: &mut Commands, mats: Res<NaturalResourceMaterials>) {
-    let mut rng = rand::thread_rng();
-    for x in -1000..=1000 {
-        for y in -1000..=1000 {
-            if rng.gen_ratio(1, 100 * 100) {
-                // mats.
-                let r = NaturalResourceType::choose(&mut rng);
-                let mat = match r {
-                    NaturalResourceType::Coal => mats.coal.clone(),
-                    NaturalResourceType::Iron => mats.iron.clone(),
-                    NaturalResourceType::Gold => mats.gold.clone(),
-                    NaturalResourceType::Wood => mats.wood.clone(),
-                    NaturalResourceType::Water => mats.water.clone(),
-                };
-                commands
-                    .spawn(SpriteBundle {
-                        sprite: Sprite::new(vec2(10., 10.)),
-                        material: mat,
-                        transform: Transform::from_translation(vec3(x as f32, y as f32, 100.)),
-                        ..Default::default()
-                    })
-                    .with(NaturalResource(r));
+/// Uniform random point within `radius` of `center`.
+fn point_in_disk<R: Rng>(rng: &mut R, center: Vec2, radius: f32) -> Vec2 {
+    let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+    let distance = radius * rng.gen_range(0.0f32..1.).sqrt();
+    center + vec2(angle.cos(), angle.sin()) * distance
+}
+
+fn plant_cluster<R: Rng>(
+    commands: &mut Commands,
+    mats: &NaturalResourceMaterials,
+    rng: &mut R,
+    nr_type: NaturalResourceType,
+    center: Vec2,
+) {
+    let (count, spread) = nr_type.cluster_shape();
+    for _ in 0..rng.gen_range(count) {
+        // averaging two samples bunches the nodes towards the middle of the cluster
+        let offset = (point_in_disk(rng, Vec2::zero(), spread)
+            + point_in_disk(rng, Vec2::zero(), spread))
+            / 2.;
+        spawn_resource_node(commands, mats, nr_type, center + offset);
+    }
+}
+
+fn spawn_resource_node(
+    commands: &mut Commands,
+    mats: &NaturalResourceMaterials,
+    nr_type: NaturalResourceType,
+    position: Vec2,
+) {
+    commands
+        .spawn(SpriteBundle {
+            sprite: Sprite::new(vec2(10., 10.)),
+            material: mats.get(nr_type),
+            transform: Transform::from_translation(vec3(position.x, position.y, 100.)),
+            ..Default::default()
+        })
+        .with(NaturalResource(nr_type));
+}
+
+/// Plants groves and veins on and just around every island, in the mix its biome sets with ores
+/// made scarcer by `resource_rarity`.
+pub fn plant_resources(
+    commands: &mut Commands,
+    mats: Res<NaturalResourceMaterials>,
+    config: Res<GameConfig>,
+    mut world_rng: ResMut<WorldRng>,
+    islands_query: Query<(&Transform, &Size, &Biome), With<Island>>,
+) {
+    let rng = &mut world_rng.0;
+    for (transform, size, biome) in islands_query.iter() {
+        let center = vec2(transform.translation.x, transform.translation.y);
+        let radius = size.width / 2.;
+        let weights = biome.resource_weights();
+        let distribution = WeightedIndex::new(weights.iter().map(|(nr_type, weight)| {
+            if nr_type.is_ore() {
+                *weight as f32 / config.resource_rarity
+            } else {
+                *weight as f32
+            }
+        }))
+        .unwrap();
+
+        let (min_clusters, max_clusters) = config.resource_clusters;
+        for _ in 0..rng.gen_range(min_clusters..max_clusters) {
+            let nr_type = weights[distribution.sample(rng)].0;
+            let cluster_center = point_in_disk(rng, center, radius * 1.1);
+            plant_cluster(commands, &mats, rng, nr_type, cluster_center);
+        }
+        let gold_chance = biome.gold_vein_chance() / config.resource_rarity as f64;
+        if rng.gen_bool(gold_chance.min(1.)) {
+            let vein_center = point_in_disk(rng, center, radius);
+            plant_cluster(commands, &mats, rng, NaturalResourceType::Gold, vein_center);
+        }
+    }
+}
+
+/// Every so often each Wood and Water node may seed a new one next to it, as fast as the
+/// weather allows.
+fn regrow_resources(
+    commands: &mut Commands,
+    time: Res<GameTime>,
+    weather: Res<Weather>,
+    mut world_rng: ResMut<WorldRng>,
+    mut timer: ResMut<RegrowthTimer>,
+    mats: Res<NaturalResourceMaterials>,
+    query: Query<(&Transform, &NaturalResource)>,
+) {
+    if !timer.0.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+    let rng = &mut world_rng.0;
+    for nr_type in [NaturalResourceType::Wood, NaturalResourceType::Water].iter() {
+        let nodes: Vec<Vec2> = query
+            .iter()
+            .filter(|(_, nr)| nr.0 == *nr_type)
+            .map(|(transform, _)| transform.translation.truncate())
+            .collect();
+        if nodes.len() >= MAX_REGROWN_NODES {
+            continue;
+        }
+        let chance = (REGROWTH_CHANCE * weather.current.regrowth_factor(*nr_type) as f64).min(1.);
+        for node in nodes.iter() {
+            if rng.gen_bool(chance) {
+                let position = point_in_disk(rng, *node, REGROWTH_SPREAD);
+                spawn_resource_node(commands, &mats, *nr_type, position);
             }
         }
     }
 }
+
 pub struct ResourcesPlugin;
 impl Plugin for ResourcesPlugin {
     fn build(&self, app: &mut AppBuilder) {
@@ -97,7 +213,9 @@ impl Plugin for ResourcesPlugin {
             "resources",
             SystemStage::single(make_resource_materials.system()),
         )
-        .add_startup_stage_after("resources", "planting_resources", SystemStage::parallel())
-        .add_startup_system_to_stage("planting_resources", plant_resources.system());
+        // resources are planted around islands, so they have to exist first
+        .add_world_gen_system(MyStages::Resources, plant_resources.system())
+        .add_resource(RegrowthTimer(Timer::from_seconds(REGROWTH_INTERVAL, true)))
+        .add_game_system(regrow_resources.system());
     }
 }
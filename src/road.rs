@@ -0,0 +1,357 @@
+use std::collections::VecDeque;
+
+use bevy::{math::vec3, prelude::*};
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    building::{can_afford, format_cost, pay, Building},
+    command::GameCommand,
+    faction::{Factions, Owner},
+    island::{Island, IslandNR, Size as IslandSize},
+    person::TargetPosition,
+    resource::{NaturalResource, NaturalResourceType},
+    CursorPosition, GameStates, Materials,
+};
+
+/// How many times faster people walk on roads.
+pub const ROAD_SPEED_FACTOR: f32 = 2.;
+/// Road length one Wood pays for.
+const ROAD_LENGTH_PER_WOOD: f32 = 25.;
+const ROAD_WIDTH: f32 = 4.;
+/// Road ends snap to buildings, resources and other roads this close to the cursor.
+const SNAP_RADIUS: f32 = 15.;
+/// Road ends closer than this share a junction.
+const JUNCTION_RADIUS: f32 = 3.;
+/// A route over roads has to save at least this share of the walk to be worth the detour.
+const MIN_SAVING: f32 = 0.05;
+
+/// An island's roads: junctions and the straight stretches between them.
+#[derive(Debug, Default)]
+pub struct RoadNetwork {
+    pub nodes: Vec<Vec2>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+impl RoadNetwork {
+    fn node_at(&mut self, position: Vec2) -> usize {
+        if let Some(i) = self
+            .nodes
+            .iter()
+            .position(|node| (*node - position).length() <= JUNCTION_RADIUS)
+        {
+            return i;
+        }
+        self.nodes.push(position);
+        self.nodes.len() - 1
+    }
+
+    pub fn add_road(&mut self, from: Vec2, to: Vec2) {
+        let a = self.node_at(from);
+        let b = self.node_at(to);
+        if a != b && !self.edges.contains(&(a, b)) && !self.edges.contains(&(b, a)) {
+            self.edges.push((a, b));
+        }
+    }
+
+    pub fn is_on_road(&self, position: Vec2) -> bool {
+        self.edges.iter().any(|(a, b)| {
+            distance_to_segment(position, self.nodes[*a], self.nodes[*b]) <= ROAD_WIDTH
+        })
+    }
+
+    /// Junctions to pass on the way from `from` to `to`, if going by road is faster than walking
+    /// straight there. Getting on and off the road network is on foot.
+    pub fn route(&self, from: Vec2, to: Vec2) -> Option<Vec<Vec2>> {
+        if self.edges.is_empty() {
+            return None;
+        }
+        // measured in walking distance, so a road counts for less than its length
+        let direct = (to - from).length();
+        let mut cost: Vec<f32> = self
+            .nodes
+            .iter()
+            .map(|node| (*node - from).length())
+            .collect();
+        let mut previous: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let mut done = vec![false; self.nodes.len()];
+        while let Some(current) = (0..self.nodes.len())
+            .filter(|i| !done[*i])
+            .min_by(|a, b| cost[*a].partial_cmp(&cost[*b]).unwrap())
+        {
+            done[current] = true;
+            for (a, b) in self.edges.iter() {
+                let next = match (*a == current, *b == current) {
+                    (true, _) => *b,
+                    (_, true) => *a,
+                    _ => continue,
+                };
+                let through =
+                    cost[current] + (self.nodes[*a] - self.nodes[*b]).length() / ROAD_SPEED_FACTOR;
+                if through < cost[next] {
+                    cost[next] = through;
+                    previous[next] = Some(current);
+                }
+            }
+        }
+
+        let (exit, total) = (0..self.nodes.len())
+            .map(|i| (i, cost[i] + (to - self.nodes[i]).length()))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())?;
+        if total > direct * (1. - MIN_SAVING) {
+            return None;
+        }
+        let mut path = vec![self.nodes[exit]];
+        let mut node = exit;
+        while let Some(before) = previous[node] {
+            path.push(self.nodes[before]);
+            node = before;
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let along = b - a;
+    let t = if along.length_squared() > 0. {
+        ((point - a).dot(along) / along.length_squared())
+            .max(0.)
+            .min(1.)
+    } else {
+        0.
+    };
+    (point - (a + along * t)).length()
+}
+
+pub fn road_cost(from: Vec2, to: Vec2) -> Vec<(NaturalResourceType, u32)> {
+    let wood = ((to - from).length() / ROAD_LENGTH_PER_WOOD).ceil().max(1.) as u32;
+    vec![(NaturalResourceType::Wood, wood)]
+}
+
+/// Junctions a person still has to pass, nearest first, before heading for their target.
+#[derive(Debug, Default)]
+pub struct Route(pub VecDeque<Vec2>);
+
+pub struct RoadSegment;
+
+/// Where the road being laid out with `R` starts.
+#[derive(Debug, Default)]
+pub struct RoadDraft(pub Option<Vec2>);
+
+/// Plans a route over roads for everyone who got somewhere new to go.
+fn plan_routes(
+    islands_query: Query<(&GlobalTransform, &IslandSize, &RoadNetwork), With<Island>>,
+    mut people_query: Query<
+        (&GlobalTransform, &TargetPosition, &mut Route),
+        Changed<TargetPosition>,
+    >,
+) {
+    for (transform, target, mut route) in people_query.iter_mut() {
+        route.0.clear();
+        let target = match target.0 {
+            Some(target) => target,
+            None => continue,
+        };
+        let position = transform.translation.truncate();
+        let network = islands_query.iter().find(|(island_transform, size, _)| {
+            (position - island_transform.translation.truncate()).length() <= size.width / 2.
+        });
+        if let Some((_, _, network)) = network {
+            if let Some(path) = network.route(position, target) {
+                route.0.extend(path);
+            }
+        }
+    }
+}
+
+/// `R` over a building, resource or road starts a road, `R` again ends it there.
+fn road_controls(
+    keys: Res<Input<KeyCode>>,
+    cursor: Res<CursorPosition>,
+    factions: Res<Factions>,
+    mut draft: ResMut<RoadDraft>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+    islands_query: Query<(Entity, &GlobalTransform, &IslandSize, &RoadNetwork), With<Island>>,
+    buildings_query: Query<&GlobalTransform, With<Building>>,
+    resources_query: Query<&GlobalTransform, With<NaturalResource>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        draft.0 = None;
+    }
+    if !keys.just_pressed(KeyCode::R) {
+        return;
+    }
+    let junctions = islands_query
+        .iter()
+        .flat_map(|(_, _, _, network)| network.nodes.iter().copied());
+    let snapped = buildings_query
+        .iter()
+        .chain(resources_query.iter())
+        .map(|transform| transform.translation.truncate())
+        .chain(junctions)
+        .map(|point| (point, (point - cursor.world).length()))
+        .filter(|(_, distance)| *distance <= SNAP_RADIUS)
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map_or(cursor.world, |(point, _)| point);
+
+    let from = match draft.0.take() {
+        Some(from) => from,
+        None => {
+            println!(
+                "Road starts at ({:.0}, {:.0}), press R at its end",
+                snapped.x, snapped.y
+            );
+            draft.0 = Some(snapped);
+            return;
+        }
+    };
+    let island = islands_query.iter().find(|(_, transform, size, _)| {
+        (from - transform.translation.truncate()).length() <= size.width / 2.
+    });
+    if let Some((island, _, _, _)) = island {
+        game_commands.send(GameCommand::BuildRoad {
+            faction: factions.player,
+            island,
+            from,
+            to: snapped,
+        });
+    }
+}
+
+/// Lays roads for any faction, on land of its own and paid for in Wood by the island.
+fn handle_road_commands(
+    commands: &mut Commands,
+    events: Res<Events<GameCommand>>,
+    mut event_reader: Local<EventReader<GameCommand>>,
+    materials: Res<Materials>,
+    mut islands_query: Query<
+        (
+            &GlobalTransform,
+            &IslandSize,
+            &Owner,
+            &mut IslandNR,
+            &mut RoadNetwork,
+        ),
+        With<Island>,
+    >,
+) {
+    for ev in event_reader.iter(&events) {
+        let (faction, island, from, to) = match ev {
+            GameCommand::BuildRoad {
+                faction,
+                island,
+                from,
+                to,
+            } => (*faction, *island, *from, *to),
+            _ => continue,
+        };
+        let (transform, size, owner, mut stock, mut network) = match islands_query.get_mut(island) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        if owner.0 != Some(faction) {
+            continue;
+        }
+        let center = transform.translation.truncate();
+        let on_land = |point: Vec2| (point - center).length() <= size.width / 2.;
+        if !on_land(from) || !on_land(to) {
+            println!("Roads have to stay on the island");
+            continue;
+        }
+        let cost = road_cost(from, to);
+        if !can_afford(&stock, &cost) {
+            println!("A road this long costs {}", format_cost(&cost));
+            continue;
+        }
+        pay(&mut stock, &cost);
+        network.add_road(from, to);
+        commands
+            .spawn(GeometryBuilder::build_as(
+                &shapes::Polygon {
+                    points: vec![from, to],
+                    closed: false,
+                },
+                materials.road_material.clone(),
+                TessellationMode::Stroke(StrokeOptions::default().with_line_width(ROAD_WIDTH)),
+                Transform::from_translation(vec3(0., 0., 1.)),
+            ))
+            .with(RoadSegment);
+    }
+}
+
+pub struct RoadPlugin;
+impl Plugin for RoadPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<RoadDraft>()
+            .add_game_system(road_controls.system())
+            .add_game_system(handle_road_commands.system())
+            .add_game_system(plan_routes.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(roads: &[((f32, f32), (f32, f32))]) -> RoadNetwork {
+        let mut network = RoadNetwork::default();
+        for ((ax, ay), (bx, by)) in roads.iter() {
+            network.add_road(Vec2::new(*ax, *ay), Vec2::new(*bx, *by));
+        }
+        network
+    }
+
+    #[test]
+    fn no_roads_no_route() {
+        let roads = RoadNetwork::default();
+        assert_eq!(roads.route(Vec2::zero(), Vec2::new(100., 0.)), None);
+    }
+
+    #[test]
+    fn takes_a_road_that_is_faster() {
+        let roads = network(&[((10., 0.), (190., 0.))]);
+        assert_eq!(
+            roads.route(Vec2::zero(), Vec2::new(200., 0.)),
+            Some(vec![Vec2::new(10., 0.), Vec2::new(190., 0.)])
+        );
+    }
+
+    #[test]
+    fn ignores_a_road_that_leads_away() {
+        let roads = network(&[((0., 100.), (0., 200.))]);
+        assert_eq!(roads.route(Vec2::zero(), Vec2::new(100., 0.)), None);
+    }
+
+    #[test]
+    fn needs_to_save_enough_to_be_worth_it() {
+        // a road of length x saves x / 2 of a 100 unit walk; the cutoff is at 5
+        let short = network(&[((0., 0.), (8., 0.))]);
+        assert_eq!(short.route(Vec2::zero(), Vec2::new(100., 0.)), None);
+        let long = network(&[((0., 0.), (12., 0.))]);
+        assert_eq!(
+            long.route(Vec2::zero(), Vec2::new(100., 0.)),
+            Some(vec![Vec2::zero(), Vec2::new(12., 0.)])
+        );
+    }
+
+    #[test]
+    fn picks_the_shortest_way_through_junctions() {
+        let roads = network(&[
+            ((0., 0.), (150., 150.)),
+            ((150., 150.), (300., 0.)),
+            ((0., 0.), (150., 0.)),
+            ((150., 0.), (300., 0.)),
+        ]);
+        assert_eq!(
+            roads.route(Vec2::zero(), Vec2::new(300., 0.)),
+            Some(vec![Vec2::zero(), Vec2::new(150., 0.), Vec2::new(300., 0.)])
+        );
+    }
+
+    #[test]
+    fn roads_share_junctions() {
+        let roads = network(&[((0., 0.), (50., 0.)), ((51., 1.), (50., 80.))]);
+        assert_eq!(roads.nodes.len(), 3);
+        assert_eq!(roads.edges, vec![(0, 1), (1, 2)]);
+    }
+}
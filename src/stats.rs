@@ -0,0 +1,342 @@
+use std::{collections::VecDeque, fs, io::Write, path::Path};
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_prototype_lyon::{entity::ShapeBundle, prelude::*};
+use serde_json::json;
+
+use crate::{
+    faction::{Factions, Owner},
+    feed::GameEvent,
+    house::House,
+    island::{Island, IslandNR, Title},
+    panel::{SelectedIsland, PANEL_HEIGHT, PANEL_WIDTH},
+    person::Person,
+    resource::NaturalResourceType,
+    GameStates, GameTime, MainCamera,
+};
+
+const SAMPLE_INTERVAL: f32 = 5.;
+const HISTORY_LEN: usize = 120;
+const CHART_HEIGHT: f32 = 120.;
+const EXPORT_DIR: &str = "stats";
+
+#[derive(Debug, Clone)]
+pub struct StatsSample {
+    pub time: f64,
+    /// Stock per resource, in the order of `NaturalResourceType::ALL`.
+    pub resources: [u32; 5],
+    pub population: usize,
+    pub houses: usize,
+    pub gathers_per_minute: f32,
+}
+
+impl StatsSample {
+    pub fn total_stock(&self) -> u32 {
+        self.resources.iter().sum()
+    }
+}
+
+/// Bounded history of an island's economy, oldest sample first.
+#[derive(Debug, Default)]
+pub struct IslandHistory {
+    pub samples: VecDeque<StatsSample>,
+}
+
+pub struct StatsTimer(Timer);
+
+pub struct ChartMaterials {
+    pub background: Handle<ColorMaterial>,
+    pub stock: Handle<ColorMaterial>,
+    pub population: Handle<ColorMaterial>,
+    pub houses: Handle<ColorMaterial>,
+    pub gathers: Handle<ColorMaterial>,
+}
+
+pub struct IslandChart;
+
+pub fn count_houses(island: Entity, houses_query: &Query<&House>) -> usize {
+    houses_query
+        .iter()
+        .filter(|house| house.island == island)
+        .count()
+}
+
+pub fn count_population(
+    island: Entity,
+    houses_query: &Query<&House>,
+    people_query: &Query<&Person>,
+) -> usize {
+    people_query
+        .iter()
+        .filter(|person| {
+            person
+                .house
+                .and_then(|house| houses_query.get(house).ok())
+                .map_or(false, |house| house.island == island)
+        })
+        .count()
+}
+
+fn make_chart_materials(commands: &mut Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    commands.insert_resource(ChartMaterials {
+        background: materials.add(Color::rgba(0., 0., 0., 0.5).into()),
+        stock: materials.add(Color::YELLOW.into()),
+        population: materials.add(Color::PINK.into()),
+        houses: materials.add(Color::rgb(0.3, 0.5, 1.).into()),
+        gathers: materials.add(Color::GREEN.into()),
+    });
+}
+
+fn track_new_islands(
+    commands: &mut Commands,
+    query: Query<Entity, (With<Island>, Without<IslandHistory>)>,
+) {
+    for island in query.iter() {
+        commands.insert_one(island, IslandHistory::default());
+    }
+}
+
+fn sample_island_stats(
+    time: Res<GameTime>,
+    mut timer: ResMut<StatsTimer>,
+    events: Res<Events<GameEvent>>,
+    mut event_reader: Local<EventReader<GameEvent>>,
+    mut gathers: Local<HashMap<Entity, u32>>,
+    houses_query: Query<&House>,
+    people_query: Query<&Person>,
+    mut islands_query: Query<(Entity, &IslandNR, &mut IslandHistory)>,
+) {
+    for ev in event_reader.iter(&events) {
+        if let GameEvent::ResourceGathered {
+            island: Some(island),
+            ..
+        } = ev
+        {
+            *gathers.entry(*island).or_insert(0) += 1;
+        }
+    }
+
+    if !timer.0.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+
+    for (island, island_nr, mut history) in islands_query.iter_mut() {
+        let mut resources = [0; 5];
+        for (i, nr_type) in NaturalResourceType::ALL.iter().enumerate() {
+            resources[i] = island_nr.0.get(nr_type).copied().unwrap_or(0);
+        }
+        let gathered = gathers.remove(&island).unwrap_or(0);
+
+        history.samples.push_back(StatsSample {
+            time: time.seconds_since_startup(),
+            resources,
+            population: count_population(island, &houses_query, &people_query),
+            houses: count_houses(island, &houses_query),
+            gathers_per_minute: gathered as f32 * 60. / SAMPLE_INTERVAL,
+        });
+        if history.samples.len() > HISTORY_LEN {
+            history.samples.pop_front();
+        }
+    }
+}
+
+fn chart_line(
+    samples: &VecDeque<StatsSample>,
+    value: impl Fn(&StatsSample) -> f32,
+    material: Handle<ColorMaterial>,
+) -> ShapeBundle {
+    let max = samples.iter().map(&value).fold(1., f32::max);
+    let step = PANEL_WIDTH / (HISTORY_LEN - 1) as f32;
+    let points = samples
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            Vec2::new(
+                -PANEL_WIDTH / 2. + i as f32 * step,
+                -CHART_HEIGHT / 2. + value(sample) / max * CHART_HEIGHT,
+            )
+        })
+        .collect();
+
+    GeometryBuilder::build_as(
+        &shapes::Polygon {
+            points,
+            closed: false,
+        },
+        material,
+        TessellationMode::Stroke(StrokeOptions::default().with_line_width(2.)),
+        Transform::from_translation(Vec3::new(0., 0., 0.1)),
+    )
+}
+
+/// Redraws the chart under the island panel whenever the selection or its history changes.
+/// The chart is parented to the camera so it stays in place on screen.
+fn draw_island_chart(
+    commands: &mut Commands,
+    factions: Res<Factions>,
+    selected: Res<SelectedIsland>,
+    windows: Res<Windows>,
+    chart_materials: Res<ChartMaterials>,
+    mut last_drawn: Local<Option<(Entity, f64)>>,
+    camera_query: Query<Entity, With<MainCamera>>,
+    charts_query: Query<Entity, With<IslandChart>>,
+    islands_query: Query<(&IslandHistory, &Owner)>,
+) {
+    // other factions' islands aren't charted, their history isn't the player's to see
+    let history = selected.0.and_then(|island| {
+        islands_query
+            .get(island)
+            .ok()
+            .filter(|(_, owner)| owner.0 == Some(factions.player))
+            .map(|(history, _)| (island, history))
+    });
+    let key = history
+        .and_then(|(island, history)| history.samples.back().map(|sample| (island, sample.time)));
+    if *last_drawn == key {
+        return;
+    }
+    *last_drawn = key;
+
+    for chart in charts_query.iter() {
+        commands.despawn_recursive(chart);
+    }
+
+    let (samples, camera, window) =
+        match (history, camera_query.iter().next(), windows.get_primary()) {
+            (Some((_, history)), Some(camera), Some(window)) if history.samples.len() > 1 => {
+                (&history.samples, camera, window)
+            }
+            _ => return,
+        };
+
+    let x = -(window.width() as f32) / 2. + 10. + PANEL_WIDTH / 2.;
+    let y = window.height() as f32 / 2. - 20. - PANEL_HEIGHT - CHART_HEIGHT / 2.;
+    let background = shapes::Rectangle {
+        width: PANEL_WIDTH,
+        height: CHART_HEIGHT,
+        origin: shapes::RectangleOrigin::Center,
+    };
+
+    let chart = commands
+        .spawn(GeometryBuilder::build_as(
+            &background,
+            chart_materials.background.clone(),
+            TessellationMode::Fill(FillOptions::default()),
+            Transform::from_translation(Vec3::new(x, y, -1.)),
+        ))
+        .with(IslandChart)
+        .with_children(|parent| {
+            parent
+                .spawn(chart_line(
+                    samples,
+                    |sample| sample.total_stock() as f32,
+                    chart_materials.stock.clone(),
+                ))
+                .spawn(chart_line(
+                    samples,
+                    |sample| sample.population as f32,
+                    chart_materials.population.clone(),
+                ))
+                .spawn(chart_line(
+                    samples,
+                    |sample| sample.houses as f32,
+                    chart_materials.houses.clone(),
+                ))
+                .spawn(chart_line(
+                    samples,
+                    |sample| sample.gathers_per_minute,
+                    chart_materials.gathers.clone(),
+                ));
+        })
+        .current_entity()
+        .unwrap();
+    commands.push_children(camera, &[chart]);
+}
+
+fn write_csv(path: &Path, rows: &[(String, &IslandHistory)]) -> std::io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    write!(file, "island,time,population,houses,gathers_per_minute")?;
+    for nr_type in NaturalResourceType::ALL.iter() {
+        write!(file, ",{:?}", nr_type)?;
+    }
+    writeln!(file)?;
+    for (title, history) in rows {
+        for sample in history.samples.iter() {
+            write!(
+                file,
+                "\"{}\",{:.1},{},{},{:.1}",
+                title.replace('"', "\"\""),
+                sample.time,
+                sample.population,
+                sample.houses,
+                sample.gathers_per_minute
+            )?;
+            for count in sample.resources.iter() {
+                write!(file, ",{}", count)?;
+            }
+            writeln!(file)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_json(path: &Path, rows: &[(String, &IslandHistory)]) -> std::io::Result<()> {
+    let islands: Vec<_> = rows
+        .iter()
+        .map(|(title, history)| {
+            let samples: Vec<_> = history
+                .samples
+                .iter()
+                .map(|sample| {
+                    let resources: serde_json::Map<_, _> = NaturalResourceType::ALL
+                        .iter()
+                        .zip(sample.resources.iter())
+                        .map(|(nr_type, count)| (format!("{:?}", nr_type), json!(count)))
+                        .collect();
+                    json!({
+                        "time": sample.time,
+                        "population": sample.population,
+                        "houses": sample.houses,
+                        "gathers_per_minute": sample.gathers_per_minute,
+                        "resources": resources,
+                    })
+                })
+                .collect();
+            json!({ "island": title, "samples": samples })
+        })
+        .collect();
+    let file = fs::File::create(path)?;
+    serde_json::to_writer_pretty(file, &json!({ "islands": islands }))?;
+    Ok(())
+}
+
+fn export_stats(keys: Res<Input<KeyCode>>, islands_query: Query<(&Title, &IslandHistory)>) {
+    if !keys.just_pressed(KeyCode::E) {
+        return;
+    }
+    let rows: Vec<(String, &IslandHistory)> = islands_query
+        .iter()
+        .map(|(title, history)| (title.0.clone(), history))
+        .collect();
+
+    let dir = Path::new(EXPORT_DIR);
+    let result = fs::create_dir_all(dir)
+        .and_then(|_| write_csv(&dir.join("economy.csv"), &rows))
+        .and_then(|_| write_json(&dir.join("economy.json"), &rows));
+    match result {
+        Ok(()) => println!("Exported economy stats to {}/", EXPORT_DIR),
+        Err(e) => eprintln!("Failed to export economy stats: {}", e),
+    }
+}
+
+pub struct StatsPlugin;
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(StatsTimer(Timer::from_seconds(SAMPLE_INTERVAL, true)))
+            .add_startup_system(make_chart_materials.system())
+            .add_game_system(track_new_islands.system())
+            .add_game_system(sample_island_stats.system())
+            .add_game_system(draw_island_chart.system())
+            .add_game_system(export_stats.system());
+    }
+}
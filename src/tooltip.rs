@@ -0,0 +1,358 @@
+use bevy::prelude::*;
+
+use crate::{
+    boat::{Boat, BoatState},
+    building::Building,
+    faction::{Factions, Owner},
+    fog::{FogOfWar, Sight},
+    house::House,
+    island::{Island, IslandNR, Size as IslandSize, Title},
+    orders::describe_task,
+    person::{Person, TargetPosition},
+    resource::{NaturalResource, NaturalResourceType},
+    ui::{text_bundle, UiFocus, UiFont},
+    weather::Damage,
+    workshop::Workshop,
+    CursorPosition, GameStates, Materials, MyStages,
+};
+
+const TOOLTIP_LINES: usize = 6;
+const TOOLTIP_LINE_HEIGHT: f32 = 16.;
+const TOOLTIP_WIDTH: f32 = 260.;
+/// How far from the cursor the tooltip sits, in pixels.
+const TOOLTIP_OFFSET: f32 = 16.;
+/// Small sprites are easier to hover with a little slack around them.
+const HOVER_SLACK: f32 = 2.;
+/// Resource nodes this close together are counted as one grove or vein.
+const CLUSTER_RADIUS: f32 = 30.;
+/// Residents listed by name before the rest are only counted.
+const LISTED_RESIDENTS: usize = 3;
+
+/// Root node and every text line of the tooltip, so they can be shown and hidden together.
+pub struct TooltipPart;
+pub struct TooltipLine(pub usize);
+
+fn setup_tooltip(commands: &mut Commands, font: Res<UiFont>, materials: Res<Materials>) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(
+                    Val::Px(TOOLTIP_WIDTH),
+                    Val::Px(TOOLTIP_LINES as f32 * TOOLTIP_LINE_HEIGHT + 8.),
+                ),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                padding: Rect::all(Val::Px(4.)),
+                ..Default::default()
+            },
+            material: materials.transparent.clone(),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .with(TooltipPart)
+        .with_children(|parent| {
+            for i in 0..TOOLTIP_LINES {
+                let mut line = text_bundle(&font, "", 13.);
+                line.style.size = Size::new(Val::Auto, Val::Px(TOOLTIP_LINE_HEIGHT));
+                line.visible.is_visible = false;
+                parent.spawn(line).with(TooltipPart).with(TooltipLine(i));
+            }
+        });
+}
+
+fn is_under(cursor: Vec2, transform: &GlobalTransform, sprite: &Sprite) -> bool {
+    let offset = cursor - transform.translation.truncate();
+    offset.x.abs() <= sprite.size.x / 2. + HOVER_SLACK
+        && offset.y.abs() <= sprite.size.y / 2. + HOVER_SLACK
+}
+
+fn family_of(
+    house: Option<Entity>,
+    houses_query: &Query<(&GlobalTransform, &Sprite, &House)>,
+) -> String {
+    match house.and_then(|house| houses_query.get(house).ok()) {
+        Some((_, _, house)) => format!("the {} house", house.family),
+        None => "homeless".to_string(),
+    }
+}
+
+/// Describes whatever is under the cursor, smallest things first: people, boats, resources,
+/// buildings and finally islands. Only what the player has discovered is described.
+fn hovered_lines(
+    cursor: Vec2,
+    factions: &Factions,
+    fog: &FogOfWar,
+    people_query: &Query<(
+        &GlobalTransform,
+        &Sprite,
+        &Visible,
+        &Person,
+        &TargetPosition,
+    )>,
+    boats_query: &Query<(&GlobalTransform, &Sprite, &Visible, &Boat)>,
+    resources_query: &Query<(&GlobalTransform, &Sprite, &Visible, &NaturalResource)>,
+    houses_query: &Query<(&GlobalTransform, &Sprite, &House)>,
+    buildings_query: &Query<(
+        &GlobalTransform,
+        &Sprite,
+        &Building,
+        Option<&Workshop>,
+        Option<&Damage>,
+    )>,
+    islands_query: &Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &IslandSize,
+            &Title,
+            &Owner,
+            &IslandNR,
+        ),
+        With<Island>,
+    >,
+) -> Option<Vec<String>> {
+    let person = people_query
+        .iter()
+        .find(|(transform, sprite, visible, _, _)| {
+            visible.is_visible && is_under(cursor, transform, sprite)
+        });
+    if let Some((_, _, _, person, target)) = person {
+        return Some(vec![
+            person.name.clone(),
+            describe_task(&person.task, target),
+            format!("Home: {}", family_of(person.house, houses_query)),
+        ]);
+    }
+
+    let boat = boats_query.iter().find(|(transform, sprite, visible, _)| {
+        visible.is_visible && is_under(cursor, transform, sprite)
+    });
+    if let Some((_, _, _, boat)) = boat {
+        let state = match boat.state {
+            BoatState::Docked => "docked",
+            BoatState::Sailing(_) => "sailing",
+            BoatState::Arrived(_) => "unloading",
+            BoatState::Returning => "returning",
+        };
+        let cargo = boat
+            .cargo
+            .iter()
+            .map(|(nr_type, amount)| format!("{} {:?}", amount, nr_type))
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Some(vec![
+            format!("Boat, {}", state),
+            format!("Passengers: {}", boat.passengers.len()),
+            format!("Cargo: {}", if cargo.is_empty() { "none" } else { &cargo }),
+        ]);
+    }
+
+    let resource = resources_query
+        .iter()
+        .find(|(transform, sprite, visible, _)| {
+            visible.is_visible && is_under(cursor, transform, sprite)
+        });
+    if let Some((transform, _, _, resource)) = resource {
+        let position = transform.translation.truncate();
+        let nearby = resources_query
+            .iter()
+            .filter(|(other, _, visible, other_resource)| {
+                visible.is_visible
+                    && other_resource.0 == resource.0
+                    && (other.translation.truncate() - position).length() <= CLUSTER_RADIUS
+            })
+            .count();
+        let kind = match resource.0 {
+            NaturalResourceType::Wood => "grove",
+            NaturalResourceType::Water => "spring",
+            _ => "vein",
+        };
+        return Some(vec![
+            format!("{:?}", resource.0),
+            format!("1 {:?} to gather here", resource.0),
+            format!("{} in this {}", nearby, kind),
+        ]);
+    }
+
+    let explored = |transform: &GlobalTransform| {
+        fog.sight(factions.player, transform.translation.truncate()) != Sight::Unexplored
+    };
+    let house = houses_query
+        .iter()
+        .find(|(transform, sprite, _)| explored(transform) && is_under(cursor, transform, sprite));
+    if let Some((_, _, house)) = house {
+        let names: Vec<String> = house
+            .residents
+            .iter()
+            .filter_map(|resident| people_query.get(*resident).ok())
+            .map(|(_, _, _, person, _)| person.name.clone())
+            .collect();
+        let mut residents = names
+            .iter()
+            .take(LISTED_RESIDENTS)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        if names.len() > LISTED_RESIDENTS {
+            residents.push_str(&format!(" and {} more", names.len() - LISTED_RESIDENTS));
+        }
+        return Some(vec![
+            format!("The {} {:?}", house.family, house.tier),
+            format!("Residents: {}/{}", house.residents.len(), house.capacity()),
+            if residents.is_empty() {
+                "Nobody lives here".to_string()
+            } else {
+                residents
+            },
+        ]);
+    }
+
+    let building = buildings_query.iter().find(|(transform, sprite, _, _, _)| {
+        explored(transform) && is_under(cursor, transform, sprite)
+    });
+    if let Some((_, _, building, workshop, damage)) = building {
+        let mut lines = vec![building.0.name()];
+        if let Some(workshop) = workshop {
+            lines.push(workshop.describe());
+        }
+        if let Some(damage) = damage.filter(|damage| damage.0 > 0) {
+            lines.push(format!("Storm damage: {}", damage.0));
+        }
+        return Some(lines);
+    }
+
+    let map = fog.map(factions.player)?;
+    let island = islands_query
+        .iter()
+        .find(|(island, transform, size, _, _, _)| {
+            map.knows_island(*island)
+                && (cursor - transform.translation.truncate()).length() <= size.width / 2.
+        });
+    island.map(|(island, transform, _, title, owner, island_nr)| {
+        // stock is only known as of the last time someone looked
+        let live = owner.0 == Some(factions.player)
+            || map.sight_at(transform.translation.truncate()) == Sight::Watched;
+        let stock = if live {
+            Some(&island_nr.0)
+        } else {
+            map.last_seen(island)
+        };
+        let stock = NaturalResourceType::ALL
+            .iter()
+            .map(|nr_type| {
+                format!(
+                    "{:?} {}",
+                    nr_type,
+                    stock
+                        .and_then(|stock| stock.get(nr_type))
+                        .copied()
+                        .unwrap_or(0)
+                )
+            })
+            .collect::<Vec<_>>();
+        vec![
+            if live {
+                title.0.clone()
+            } else {
+                format!("{} (last seen)", title.0)
+            },
+            format!("Owner: {}", factions.name_of(*owner)),
+            stock[..3].join(", "),
+            stock[3..].join(", "),
+        ]
+    })
+}
+
+/// Follows the cursor with a description of what is under it, and hides over the UI or open sea.
+fn update_tooltip(
+    cursor: Res<CursorPosition>,
+    ui_focus: Res<UiFocus>,
+    windows: Res<Windows>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    people_query: Query<(
+        &GlobalTransform,
+        &Sprite,
+        &Visible,
+        &Person,
+        &TargetPosition,
+    )>,
+    boats_query: Query<(&GlobalTransform, &Sprite, &Visible, &Boat)>,
+    resources_query: Query<(&GlobalTransform, &Sprite, &Visible, &NaturalResource)>,
+    houses_query: Query<(&GlobalTransform, &Sprite, &House)>,
+    buildings_query: Query<(
+        &GlobalTransform,
+        &Sprite,
+        &Building,
+        Option<&Workshop>,
+        Option<&Damage>,
+    )>,
+    islands_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &IslandSize,
+            &Title,
+            &Owner,
+            &IslandNR,
+        ),
+        With<Island>,
+    >,
+    mut parts_query: Query<(&mut Visible, &mut Style), With<TooltipPart>>,
+    mut lines_query: Query<(&mut Text, &TooltipLine)>,
+) {
+    let lines = match (cursor.screen, ui_focus.hovered) {
+        (Some(_), false) => hovered_lines(
+            cursor.world,
+            &factions,
+            &fog,
+            &people_query,
+            &boats_query,
+            &resources_query,
+            &houses_query,
+            &buildings_query,
+            &islands_query,
+        ),
+        _ => None,
+    };
+
+    let position = match (cursor.screen, windows.get_primary()) {
+        (Some(screen), Some(window)) => Rect {
+            left: Val::Px(screen.x + window.width() / 2. + TOOLTIP_OFFSET),
+            top: Val::Px(window.height() / 2. - screen.y + TOOLTIP_OFFSET),
+            ..Default::default()
+        },
+        _ => Rect::default(),
+    };
+    for (mut visible, mut style) in parts_query.iter_mut() {
+        if visible.is_visible != lines.is_some() {
+            visible.is_visible = lines.is_some();
+        }
+        // only the root is positioned absolutely, the lines flow inside it
+        if style.position_type == PositionType::Absolute && lines.is_some() {
+            style.position = position;
+        }
+    }
+
+    let lines = match lines {
+        Some(lines) => lines,
+        None => return,
+    };
+    for (mut text, line) in lines_query.iter_mut() {
+        let value = lines.get(line.0).cloned().unwrap_or_default();
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub struct TooltipPlugin;
+impl Plugin for TooltipPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_world_gen_system(MyStages::Ui, setup_tooltip.system())
+            .add_game_system(update_tooltip.system());
+    }
+}
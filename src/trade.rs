@@ -0,0 +1,536 @@
+use std::collections::VecDeque;
+
+use bevy::{math::vec3, prelude::*};
+use bevy_prototype_lyon::prelude::*;
+
+use crate::{
+    boat::{water_path, Dock},
+    command::GameCommand,
+    config::GameConfig,
+    faction::{FactionId, Factions, Owner},
+    fog::FogOfWar,
+    island::{Island, IslandNR, Size as IslandSize, Title},
+    panel::SelectedIsland,
+    resource::{NaturalResourceMaterials, NaturalResourceType},
+    ui::{text_bundle, UiFont},
+    CursorPosition, GameStates, GameTime, MyStages,
+};
+
+const DISPATCH_INTERVAL: f32 = 1.;
+const TRADE_ROWS: usize = 5;
+/// How far back the per-route throughput looks, in seconds.
+const THROUGHPUT_WINDOW: f64 = 3. * 60.;
+
+pub struct TradeRoute {
+    pub id: usize,
+    pub faction: FactionId,
+    pub from: Entity,
+    pub to: Entity,
+    pub resource: NaturalResourceType,
+    /// Water path from the source dock to the destination dock.
+    pub path: Vec<Vec2>,
+    pub in_transit: u32,
+    pub delivered: u32,
+    /// When and how much arrived, oldest first.
+    deliveries: VecDeque<(f64, u32)>,
+}
+
+impl TradeRoute {
+    /// Goods delivered per minute over the throughput window.
+    pub fn throughput(&self, now: f64) -> f32 {
+        let recent: u32 = self
+            .deliveries
+            .iter()
+            .filter(|(at, _)| now - *at <= THROUGHPUT_WINDOW)
+            .map(|(_, amount)| *amount)
+            .sum();
+        recent as f32 / (THROUGHPUT_WINDOW / 60.) as f32
+    }
+}
+
+#[derive(Default)]
+pub struct TradeRoutes {
+    pub routes: Vec<TradeRoute>,
+    next_id: usize,
+    /// Set when routes are added or removed, so their lines get redrawn.
+    dirty: bool,
+}
+
+pub struct Shipment {
+    route: usize,
+    to: Entity,
+    resource: NaturalResourceType,
+    amount: u32,
+    path: VecDeque<Vec2>,
+}
+
+pub struct TradeTimer(Timer);
+pub struct TradeRouteLine;
+pub enum TradeText {
+    Header,
+    Row(usize),
+}
+
+fn setup_trade_list(
+    commands: &mut Commands,
+    font: Res<UiFont>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.),
+                    bottom: Val::Px(220.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            material: color_materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn(text_bundle(&font, "", 14.))
+                .with(TradeText::Header);
+            for i in 0..TRADE_ROWS {
+                parent
+                    .spawn(text_bundle(&font, "", 14.))
+                    .with(TradeText::Row(i));
+            }
+        });
+}
+
+fn dock_position(
+    docks_query: &Query<(&Dock, &GlobalTransform, &Owner)>,
+    island: Entity,
+    faction: FactionId,
+) -> Option<Vec2> {
+    docks_query
+        .iter()
+        .find(|(dock, _, owner)| dock.island == island && owner.0 == Some(faction))
+        .map(|(_, transform, _)| transform.translation.truncate())
+}
+
+/// Opens and closes routes for any faction. Both ends need a dock of the faction's own.
+fn handle_trade_commands(
+    events: Res<Events<GameCommand>>,
+    mut event_reader: Local<EventReader<GameCommand>>,
+    mut trade: ResMut<TradeRoutes>,
+    docks_query: Query<(&Dock, &GlobalTransform, &Owner)>,
+    islands_query: Query<(&GlobalTransform, &IslandSize, &Owner), With<Island>>,
+) {
+    for ev in event_reader.iter(&events) {
+        match ev {
+            GameCommand::OpenTradeRoute {
+                faction,
+                from,
+                to,
+                resource,
+            } => {
+                let owned = |island: &Entity| {
+                    islands_query
+                        .get(*island)
+                        .map_or(false, |(_, _, owner)| owner.0 == Some(*faction))
+                };
+                if from == to || !owned(from) || !owned(to) {
+                    println!(
+                        "Faction {:?} can't trade between {:?} and {:?}",
+                        faction, from, to
+                    );
+                    continue;
+                }
+                let exists = trade.routes.iter().any(|route| {
+                    route.from == *from && route.to == *to && route.resource == *resource
+                });
+                if exists {
+                    continue;
+                }
+                let (start, end) = match (
+                    dock_position(&docks_query, *from, *faction),
+                    dock_position(&docks_query, *to, *faction),
+                ) {
+                    (Some(start), Some(end)) => (start, end),
+                    _ => {
+                        println!("Trade routes need a dock on both islands");
+                        continue;
+                    }
+                };
+                let obstacles: Vec<(Vec2, f32)> = islands_query
+                    .iter()
+                    .map(|(transform, size, _)| (transform.translation.truncate(), size.width / 2.))
+                    .collect();
+                let mut path = vec![start];
+                path.extend(water_path(start, end, &obstacles));
+
+                let id = trade.next_id;
+                trade.next_id += 1;
+                trade.routes.push(TradeRoute {
+                    id,
+                    faction: *faction,
+                    from: *from,
+                    to: *to,
+                    resource: *resource,
+                    path,
+                    in_transit: 0,
+                    delivered: 0,
+                    deliveries: VecDeque::new(),
+                });
+                trade.dirty = true;
+            }
+            GameCommand::CloseTradeRoute { faction, route } => {
+                let before = trade.routes.len();
+                trade
+                    .routes
+                    .retain(|r| !(r.id == *route && r.faction == *faction));
+                if trade.routes.len() != before {
+                    trade.dirty = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Ships a batch down every route whose source holds more than the threshold, one shipment at
+/// a time per route. Routes whose islands changed hands are dropped.
+fn dispatch_shipments(
+    commands: &mut Commands,
+    time: Res<GameTime>,
+    config: Res<GameConfig>,
+    nr_materials: Res<NaturalResourceMaterials>,
+    mut timer: ResMut<TradeTimer>,
+    mut trade: ResMut<TradeRoutes>,
+    owners_query: Query<&Owner, With<Island>>,
+    mut stock_query: Query<&mut IslandNR>,
+) {
+    if !timer.0.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+    let before = trade.routes.len();
+    trade.routes.retain(|route| {
+        let owned = |island: Entity| {
+            owners_query
+                .get(island)
+                .map_or(false, |owner| owner.0 == Some(route.faction))
+        };
+        owned(route.from) && owned(route.to)
+    });
+    if trade.routes.len() != before {
+        trade.dirty = true;
+    }
+
+    for route in trade.routes.iter_mut() {
+        if route.in_transit > 0 {
+            continue;
+        }
+        let mut stock = match stock_query.get_mut(route.from) {
+            Ok(stock) => stock,
+            Err(_) => continue,
+        };
+        let count = stock.0.entry(route.resource).or_insert(0);
+        if *count <= config.trade_threshold {
+            continue;
+        }
+        let amount = (*count - config.trade_threshold).min(config.trade_batch);
+        *count -= amount;
+        route.in_transit += amount;
+
+        let start = route.path[0];
+        commands
+            .spawn(SpriteBundle {
+                material: nr_materials.get(route.resource),
+                sprite: Sprite::new(Vec2::splat(6.)),
+                transform: Transform::from_translation(vec3(start.x, start.y, 25.)),
+                ..Default::default()
+            })
+            .with(Shipment {
+                route: route.id,
+                to: route.to,
+                resource: route.resource,
+                amount,
+                path: route.path.iter().skip(1).copied().collect(),
+            });
+    }
+}
+
+fn move_shipments(
+    commands: &mut Commands,
+    time: Res<GameTime>,
+    config: Res<GameConfig>,
+    mut trade: ResMut<TradeRoutes>,
+    mut shipments_query: Query<(Entity, &mut Transform, &mut Shipment)>,
+    mut stock_query: Query<&mut IslandNR>,
+) {
+    let step = time.delta_seconds() * config.boat_speed;
+    for (entity, mut transform, mut shipment) in shipments_query.iter_mut() {
+        if let Some(next) = shipment.path.front().copied() {
+            let offset = next - transform.translation.truncate();
+            if offset.length() > step {
+                transform.translation += (offset / offset.length() * step).extend(0.);
+            } else {
+                transform.translation.x = next.x;
+                transform.translation.y = next.y;
+                shipment.path.pop_front();
+            }
+            continue;
+        }
+
+        commands.despawn(entity);
+        if let Ok(mut stock) = stock_query.get_mut(shipment.to) {
+            *stock.0.entry(shipment.resource).or_insert(0) += shipment.amount;
+        }
+        let now = time.seconds_since_startup();
+        if let Some(route) = trade.routes.iter_mut().find(|r| r.id == shipment.route) {
+            route.in_transit = route.in_transit.saturating_sub(shipment.amount);
+            route.delivered += shipment.amount;
+            route.deliveries.push_back((now, shipment.amount));
+            while let Some((at, _)) = route.deliveries.front() {
+                if now - *at <= THROUGHPUT_WINDOW {
+                    break;
+                }
+                route.deliveries.pop_front();
+            }
+        }
+    }
+}
+
+fn draw_route_lines(
+    commands: &mut Commands,
+    factions: Res<Factions>,
+    mut trade: ResMut<TradeRoutes>,
+    lines_query: Query<Entity, With<TradeRouteLine>>,
+) {
+    if !trade.dirty {
+        return;
+    }
+    trade.dirty = false;
+    for line in lines_query.iter() {
+        commands.despawn(line);
+    }
+    for route in trade.routes.iter() {
+        let material = match factions.get(route.faction) {
+            Some(faction) => faction.house_material.clone(),
+            None => continue,
+        };
+        commands
+            .spawn(GeometryBuilder::build_as(
+                &shapes::Polygon {
+                    points: route.path.clone(),
+                    closed: false,
+                },
+                material,
+                TessellationMode::Stroke(StrokeOptions::default().with_line_width(2.)),
+                Transform::from_translation(vec3(0., 0., 5.)),
+            ))
+            .with(TradeRouteLine);
+    }
+}
+
+fn update_trade_list(
+    time: Res<GameTime>,
+    factions: Res<Factions>,
+    trade: Res<TradeRoutes>,
+    titles_query: Query<&Title>,
+    mut text_query: Query<(&mut Text, &TradeText)>,
+) {
+    let now = time.seconds_since_startup();
+    let name = |island: Entity| {
+        titles_query
+            .get(island)
+            .map_or_else(|_| "?".to_string(), |title| title.0.clone())
+    };
+    let rows: Vec<String> = trade
+        .routes
+        .iter()
+        .filter(|route| route.faction == factions.player)
+        .map(|route| {
+            format!(
+                "{} -> {}: {:?}, {} delivered, {:.1}/min, {} at sea",
+                name(route.from),
+                name(route.to),
+                route.resource,
+                route.delivered,
+                route.throughput(now),
+                route.in_transit
+            )
+        })
+        .collect();
+
+    for (mut text, part) in text_query.iter_mut() {
+        let value = match part {
+            TradeText::Header if rows.is_empty() => String::new(),
+            TradeText::Header => format!("Trade routes ({})", rows.len()),
+            TradeText::Row(i) => rows.get(*i).cloned().unwrap_or_default(),
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+/// `T` opens a route from the selected island to the one under the cursor, shipping the
+/// selected island's most plentiful resource; shift+`T` closes routes between them.
+fn trade_controls(
+    keys: Res<Input<KeyCode>>,
+    cursor: Res<CursorPosition>,
+    factions: Res<Factions>,
+    fog: Res<FogOfWar>,
+    selected_island: Res<SelectedIsland>,
+    trade: Res<TradeRoutes>,
+    mut game_commands: ResMut<Events<GameCommand>>,
+    islands_query: Query<(Entity, &GlobalTransform, &IslandSize, &IslandNR), With<Island>>,
+) {
+    if !keys.just_pressed(KeyCode::T) {
+        return;
+    }
+    let from = match selected_island.0 {
+        Some(from) => from,
+        None => return,
+    };
+    // routes can only lead to islands the player has discovered
+    let to = islands_query
+        .iter()
+        .find(|(island, transform, size, _)| {
+            fog.knows_island(factions.player, *island)
+                && (transform.translation.truncate() - cursor.world).length() <= size.width / 2.
+        })
+        .map(|(island, _, _, _)| island);
+    let to = match to {
+        Some(to) if to != from => to,
+        _ => return,
+    };
+
+    let faction = factions.player;
+    if keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift) {
+        for route in trade.routes.iter() {
+            if route.faction == faction && route.from == from && route.to == to {
+                game_commands.send(GameCommand::CloseTradeRoute {
+                    faction,
+                    route: route.id,
+                });
+            }
+        }
+        return;
+    }
+    let resource = islands_query.get(from).ok().and_then(|(_, _, _, stock)| {
+        NaturalResourceType::ALL
+            .iter()
+            .copied()
+            .max_by_key(|nr_type| stock.0.get(nr_type).copied().unwrap_or(0))
+    });
+    if let Some(resource) = resource {
+        game_commands.send(GameCommand::OpenTradeRoute {
+            faction,
+            from,
+            to,
+            resource,
+        });
+    }
+}
+
+pub struct TradePlugin;
+impl Plugin for TradePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<TradeRoutes>()
+            .add_resource(TradeTimer(Timer::from_seconds(DISPATCH_INTERVAL, true)))
+            .add_world_gen_system(MyStages::Ui, setup_trade_list.system())
+            .add_game_system(trade_controls.system())
+            .add_game_system(handle_trade_commands.system())
+            .add_game_system(dispatch_shipments.system())
+            .add_game_system(move_shipments.system())
+            .add_game_system(draw_route_lines.system())
+            .add_game_system(update_trade_list.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::Stage;
+
+    use super::*;
+
+    fn stock(world: &World, island: Entity) -> u32 {
+        world
+            .get::<IslandNR>(island)
+            .unwrap()
+            .0
+            .get(&NaturalResourceType::Wood)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn route_moves_goods_between_docks() {
+        let faction = FactionId(0);
+        let mut world = World::new();
+        let mut resources = Resources::default();
+        let mut island_with_dock = |x: f32, wood: u32| {
+            let island = world.spawn((
+                Island,
+                GlobalTransform::from_translation(vec3(x, 0., 0.)),
+                IslandSize {
+                    width: 100.,
+                    height: 100.,
+                },
+                Owner(Some(faction)),
+                IslandNR(
+                    [(NaturalResourceType::Wood, wood)]
+                        .iter()
+                        .copied()
+                        .collect(),
+                ),
+            ));
+            let shore = if x > 0. { x - 50. } else { x + 50. };
+            world.spawn((
+                Dock { island },
+                GlobalTransform::from_translation(vec3(shore, 0., 0.)),
+                Owner(Some(faction)),
+            ));
+            island
+        };
+        let from = island_with_dock(0., 30);
+        let to = island_with_dock(300., 0);
+        let config = GameConfig::default();
+        resources.insert(config.clone());
+        resources.insert(GameTime::default());
+        resources.insert(NaturalResourceMaterials::default());
+        resources.insert(TradeRoutes::default());
+        resources.insert(TradeTimer(Timer::from_seconds(DISPATCH_INTERVAL, true)));
+        resources.insert(Events::<GameCommand>::default());
+        resources
+            .get_mut::<Events<GameCommand>>()
+            .unwrap()
+            .send(GameCommand::OpenTradeRoute {
+                faction,
+                from,
+                to,
+                resource: NaturalResourceType::Wood,
+            });
+        let mut stage = SystemStage::serial()
+            .with_system(handle_trade_commands.system())
+            .with_system(dispatch_shipments.system())
+            .with_system(move_shipments.system());
+
+        for _ in 0..20 {
+            resources
+                .get_mut::<GameTime>()
+                .unwrap()
+                .advance(DISPATCH_INTERVAL);
+            stage.run(&mut world, &mut resources);
+        }
+
+        let trade = resources.get::<TradeRoutes>().unwrap();
+        let route = match trade.routes.as_slice() {
+            [route] => route,
+            _ => panic!("expected one route, got {}", trade.routes.len()),
+        };
+        assert!(route.delivered >= config.trade_batch);
+        assert_eq!(stock(&world, to), route.delivered);
+        // nothing is lost at sea and the source keeps its threshold
+        assert_eq!(stock(&world, from) + route.in_transit + route.delivered, 30);
+        assert!(stock(&world, from) >= config.trade_threshold);
+    }
+}
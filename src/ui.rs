@@ -0,0 +1,47 @@
+use bevy::prelude::*;
+
+use crate::MyStages;
+
+pub struct UiFont(pub Handle<Font>);
+
+/// Whether the cursor is over an interactive UI node, so world clicks can be ignored.
+#[derive(Debug, Default)]
+pub struct UiFocus {
+    pub hovered: bool,
+}
+
+pub fn setup_ui(commands: &mut Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(CameraUiBundle::default())
+        .insert_resource(UiFont(asset_server.load("fonts/DejaVuSans.ttf")));
+}
+
+pub fn text_bundle(font: &UiFont, value: &str, font_size: f32) -> TextBundle {
+    TextBundle {
+        text: Text {
+            value: value.to_string(),
+            font: font.0.clone(),
+            style: TextStyle {
+                font_size,
+                color: Color::WHITE,
+                ..Default::default()
+            },
+        },
+        ..Default::default()
+    }
+}
+
+fn track_ui_focus(mut focus: ResMut<UiFocus>, query: Query<(&Interaction, &Visible)>) {
+    focus.hovered = query
+        .iter()
+        .any(|(interaction, visible)| visible.is_visible && *interaction != Interaction::None);
+}
+
+pub struct UiPlugin;
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<UiFocus>()
+            .add_startup_system_to_stage(MyStages::PreSetup.to_str(), setup_ui.system())
+            .add_system_to_stage(stage::PRE_UPDATE, track_ui_focus.system());
+    }
+}
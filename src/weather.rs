@@ -0,0 +1,313 @@
+use bevy::{math::vec3, prelude::*};
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    building::{Building, BuildingType},
+    config::GameConfig,
+    feed::GameEvent,
+    house::House,
+    island::{Island, IslandNR},
+    person::Person,
+    resource::NaturalResourceType,
+    ui::{text_bundle, UiFont},
+    GameStates, GameTime, Materials, MyStages, WorldRng,
+};
+
+/// Seconds of warning before a storm or drought.
+const WARNING_TIME: f32 = 20.;
+const WEATHER_TICK: f32 = 5.;
+/// Chance per weather tick that a storm damages an unsheltered house.
+const STORM_DAMAGE_CHANCE: f64 = 0.15;
+/// Damage that brings a house down.
+const MAX_HOUSE_DAMAGE: u32 = 3;
+/// Wood it takes to repair one point of damage once the storm is over.
+pub const REPAIR_WOOD: u32 = 1;
+/// Houses this close to a windbreak on the same island are sheltered from storms.
+const WINDBREAK_RADIUS: f32 = 60.;
+/// Residents one Water keeps going through a drought tick.
+const RESIDENTS_PER_WATER: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeatherKind {
+    Calm,
+    Rain,
+    Storm,
+    Drought,
+}
+
+impl WeatherKind {
+    pub fn is_disaster(self) -> bool {
+        matches!(self, WeatherKind::Storm | WeatherKind::Drought)
+    }
+
+    /// How long a spell of this weather lasts, in seconds.
+    fn duration<R: Rng>(self, rng: &mut R) -> f32 {
+        match self {
+            WeatherKind::Calm => rng.gen_range(60.0..120.),
+            WeatherKind::Rain => rng.gen_range(30.0..60.),
+            WeatherKind::Storm => rng.gen_range(20.0..40.),
+            WeatherKind::Drought => rng.gen_range(45.0..90.),
+        }
+    }
+
+    /// What comes next: disasters only follow calm or rain, and never twice in a row.
+    fn next<R: Rng>(self, rng: &mut R) -> Self {
+        let options: &[(WeatherKind, u32)] = match self {
+            WeatherKind::Calm => &[
+                (WeatherKind::Calm, 3),
+                (WeatherKind::Rain, 4),
+                (WeatherKind::Storm, 2),
+                (WeatherKind::Drought, 2),
+            ],
+            WeatherKind::Rain => &[(WeatherKind::Calm, 4), (WeatherKind::Storm, 2)],
+            WeatherKind::Storm => &[(WeatherKind::Rain, 2), (WeatherKind::Calm, 3)],
+            WeatherKind::Drought => &[(WeatherKind::Calm, 1)],
+        };
+        options
+            .choose_weighted(rng, |(_, weight)| *weight)
+            .unwrap()
+            .0
+    }
+
+    /// Multiplies how fast people walk.
+    pub fn speed_factor(self) -> f32 {
+        match self {
+            WeatherKind::Rain | WeatherKind::Storm => 0.7,
+            _ => 1.,
+        }
+    }
+
+    /// Multiplies how fast groves and springs grow back.
+    pub fn regrowth_factor(self, nr_type: NaturalResourceType) -> f32 {
+        match (self, nr_type) {
+            (WeatherKind::Rain, NaturalResourceType::Wood)
+            | (WeatherKind::Rain, NaturalResourceType::Water) => 2.,
+            (WeatherKind::Drought, NaturalResourceType::Water) => 0.,
+            (WeatherKind::Drought, NaturalResourceType::Wood) => 0.25,
+            _ => 1.,
+        }
+    }
+}
+
+/// The archipelago's weather: what it is now, for how much longer, and what comes after.
+#[derive(Debug)]
+pub struct Weather {
+    pub current: WeatherKind,
+    pub remaining: f32,
+    pub forecast: WeatherKind,
+    warned: bool,
+}
+
+impl Weather {
+    fn new<R: Rng>(rng: &mut R) -> Self {
+        Self {
+            current: WeatherKind::Calm,
+            remaining: WeatherKind::Calm.duration(rng),
+            forecast: WeatherKind::Calm.next(rng),
+            warned: false,
+        }
+    }
+}
+
+pub struct WeatherTimer(Timer);
+
+/// Storm damage a house has taken.
+#[derive(Debug, Default)]
+pub struct Damage(pub u32);
+
+pub struct Windbreak {
+    pub island: Entity,
+}
+
+pub struct WeatherText;
+
+pub fn spawn_windbreak(
+    commands: &mut Commands,
+    materials: &Materials,
+    config: &GameConfig,
+    island: Entity,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            material: materials.windbreak_material.clone(),
+            sprite: Sprite::new(BuildingType::Windbreak.size(config)),
+            transform: Transform::from_translation(vec3(position.x, position.y, 0.)),
+            ..Default::default()
+        })
+        .with(Windbreak { island })
+        .with(Building(BuildingType::Windbreak))
+        .current_entity()
+        .unwrap()
+}
+
+/// Every new world starts out calm, with its weather rolled from the world's seed.
+fn setup_weather(commands: &mut Commands, mut world_rng: ResMut<WorldRng>) {
+    commands.insert_resource(Weather::new(&mut world_rng.0));
+}
+
+fn setup_weather_text(commands: &mut Commands, font: Res<UiFont>) {
+    let mut text = text_bundle(&font, "", 16.);
+    text.style.position_type = PositionType::Absolute;
+    text.style.position = Rect {
+        left: Val::Percent(40.),
+        top: Val::Px(10.),
+        ..Default::default()
+    };
+    commands.spawn(text).with(WeatherText);
+}
+
+/// Moves the weather along its own timeline, announcing disasters before they hit.
+fn advance_weather(
+    time: Res<GameTime>,
+    mut world_rng: ResMut<WorldRng>,
+    mut weather: ResMut<Weather>,
+    mut game_events: ResMut<Events<GameEvent>>,
+) {
+    weather.remaining -= time.delta_seconds();
+    if weather.forecast.is_disaster() && !weather.warned && weather.remaining <= WARNING_TIME {
+        weather.warned = true;
+        game_events.send(GameEvent::WeatherForecast {
+            weather: weather.forecast,
+            seconds: weather.remaining.max(0.).round() as u32,
+        });
+    }
+    if weather.remaining > 0. {
+        return;
+    }
+    let rng = &mut world_rng.0;
+    weather.current = weather.forecast;
+    weather.remaining = weather.current.duration(rng);
+    weather.forecast = weather.current.next(rng);
+    weather.warned = false;
+    println!("The weather turns to {:?}", weather.current);
+    game_events.send(GameEvent::WeatherChanged {
+        weather: weather.current,
+    });
+}
+
+/// Storms batter houses that no windbreak shelters and bring down the worst hit, which are
+/// repaired with Wood once the storm has passed. Droughts make islands drink from their Water
+/// stock, and without it residents leave.
+fn apply_weather(
+    commands: &mut Commands,
+    time: Res<GameTime>,
+    weather: Res<Weather>,
+    mut world_rng: ResMut<WorldRng>,
+    mut timer: ResMut<WeatherTimer>,
+    mut game_events: ResMut<Events<GameEvent>>,
+    mut houses_query: Query<(Entity, &GlobalTransform, &House, Option<&mut Damage>)>,
+    windbreaks_query: Query<(&GlobalTransform, &Windbreak)>,
+    people_query: Query<&Person>,
+    mut islands_query: Query<&mut IslandNR, With<Island>>,
+) {
+    if !timer.0.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+    let rng = &mut world_rng.0;
+    for (entity, transform, house, damage) in houses_query.iter_mut() {
+        let position = transform.translation.truncate();
+        match weather.current {
+            WeatherKind::Storm => {
+                let sheltered = windbreaks_query
+                    .iter()
+                    .any(|(windbreak_transform, windbreak)| {
+                        windbreak.island == house.island
+                            && (windbreak_transform.translation.truncate() - position).length()
+                                <= WINDBREAK_RADIUS
+                    });
+                if sheltered || !rng.gen_bool(STORM_DAMAGE_CHANCE) {
+                    continue;
+                }
+                let damage = damage.map_or(1, |damage| damage.0 + 1);
+                if damage >= MAX_HOUSE_DAMAGE {
+                    println!("A storm destroyed the {} family's house", house.family);
+                    commands.despawn_recursive(entity);
+                    game_events.send(GameEvent::HouseDestroyed {
+                        island: house.island,
+                        position,
+                    });
+                } else {
+                    commands.insert_one(entity, Damage(damage));
+                }
+            }
+            _ => {
+                let mut damage = match damage {
+                    Some(damage) if damage.0 > 0 => damage,
+                    _ => continue,
+                };
+                if let Ok(mut stock) = islands_query.get_mut(house.island) {
+                    let wood = stock.0.entry(NaturalResourceType::Wood).or_insert(0);
+                    if *wood >= REPAIR_WOOD {
+                        *wood -= REPAIR_WOOD;
+                        damage.0 -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if weather.current != WeatherKind::Drought {
+        return;
+    }
+    for (_, transform, house, _) in houses_query.iter_mut() {
+        if house.residents.is_empty() {
+            continue;
+        }
+        let mut stock = match islands_query.get_mut(house.island) {
+            Ok(stock) => stock,
+            Err(_) => continue,
+        };
+        let needed =
+            ((house.residents.len() + RESIDENTS_PER_WATER - 1) / RESIDENTS_PER_WATER) as u32;
+        let water = stock.0.entry(NaturalResourceType::Water).or_insert(0);
+        if *water >= needed {
+            *water -= needed;
+            continue;
+        }
+        *water = 0;
+        // someone gives up on the dry island
+        if let Some(leaver) = house.residents.last() {
+            if let Ok(person) = people_query.get(*leaver) {
+                println!(
+                    "{} left the {} house in the drought",
+                    person.name, house.family
+                );
+                game_events.send(GameEvent::PersonLeft {
+                    name: person.name.clone(),
+                    island: house.island,
+                    position: transform.translation.truncate(),
+                });
+            }
+            commands.despawn(*leaver);
+        }
+    }
+}
+
+fn update_weather_text(weather: Res<Weather>, mut text_query: Query<&mut Text, With<WeatherText>>) {
+    let mut value = format!(
+        "Weather: {:?} ({:.0}s)",
+        weather.current,
+        weather.remaining.max(0.)
+    );
+    if weather.warned {
+        value.push_str(&format!(", {:?} coming!", weather.forecast));
+    }
+    for mut text in text_query.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+pub struct WeatherPlugin;
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(WeatherTimer(Timer::from_seconds(WEATHER_TICK, true)))
+            .add_world_gen_system(MyStages::Ui, setup_weather.system())
+            .add_world_gen_system(MyStages::Ui, setup_weather_text.system())
+            .add_game_system(advance_weather.system())
+            .add_game_system(apply_weather.system())
+            .add_game_system(update_weather_text.system());
+    }
+}
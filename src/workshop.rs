@@ -0,0 +1,304 @@
+use bevy::{math::vec3, prelude::*, utils::HashMap};
+
+use crate::{
+    building::{Building, BuildingType},
+    clock::{DayPhase, WorldClock},
+    config::GameConfig,
+    house::House,
+    island::{IslandGoods, IslandNR},
+    orders::OrderQueue,
+    person::{Person, PersonTask, TargetPosition},
+    resource::NaturalResourceType,
+    GameStates, GameTime, Materials,
+};
+
+const WORKSHOP_UPDATE_INTERVAL: f32 = 1.;
+const MAX_WORKERS: usize = 2;
+/// Batches of inputs a workshop keeps on hand.
+pub const INPUT_BATCHES: u32 = 3;
+/// Goods a workshop holds before it has to stop and wait for them to be carried out.
+const OUTPUT_CAPACITY: u32 = 10;
+
+/// Refined goods, made in workshops and kept in `IslandGoods`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Good {
+    Tools,
+    Planks,
+    Grain,
+    Food,
+}
+
+impl Good {
+    pub const ALL: [Self; 4] = [Self::Tools, Self::Planks, Self::Grain, Self::Food];
+}
+
+/// Anything a workshop can take in.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Ware {
+    Raw(NaturalResourceType),
+    Good(Good),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WorkshopType {
+    Sawmill,
+    Smithy,
+    Farm,
+    Kitchen,
+}
+
+pub struct Recipe {
+    pub inputs: Vec<(Ware, u32)>,
+    pub output: (Good, u32),
+    /// Seconds one worker takes for a batch.
+    pub duration: f32,
+}
+
+impl WorkshopType {
+    pub fn recipe(self) -> Recipe {
+        use NaturalResourceType::*;
+        match self {
+            WorkshopType::Sawmill => Recipe {
+                inputs: vec![(Ware::Raw(Wood), 1)],
+                output: (Good::Planks, 2),
+                duration: 3.,
+            },
+            WorkshopType::Smithy => Recipe {
+                inputs: vec![(Ware::Raw(Coal), 1), (Ware::Raw(Iron), 1)],
+                output: (Good::Tools, 1),
+                duration: 6.,
+            },
+            WorkshopType::Farm => Recipe {
+                inputs: vec![(Ware::Raw(Water), 1)],
+                output: (Good::Grain, 1),
+                duration: 4.,
+            },
+            WorkshopType::Kitchen => Recipe {
+                inputs: vec![(Ware::Raw(Water), 1), (Ware::Good(Good::Grain), 1)],
+                output: (Good::Food, 2),
+                duration: 5.,
+            },
+        }
+    }
+
+    pub fn cost(self) -> Vec<(NaturalResourceType, u32)> {
+        use NaturalResourceType::*;
+        match self {
+            WorkshopType::Sawmill => vec![(Wood, 3)],
+            WorkshopType::Smithy => vec![(Wood, 3), (Iron, 2)],
+            WorkshopType::Farm => vec![(Wood, 2), (Water, 1)],
+            WorkshopType::Kitchen => vec![(Wood, 3), (Coal, 1)],
+        }
+    }
+}
+
+pub struct Workshop {
+    pub kind: WorkshopType,
+    pub island: Entity,
+    pub workers: Vec<Entity>,
+    pub input: HashMap<Ware, u32>,
+    pub output: u32,
+    /// Share of the current batch that is done.
+    pub progress: f32,
+}
+
+impl Workshop {
+    /// Short status for the island panel.
+    pub fn describe(&self) -> String {
+        let recipe = self.kind.recipe();
+        let input = recipe
+            .inputs
+            .iter()
+            .map(|(ware, _)| format!("{}", self.input.get(ware).copied().unwrap_or(0)))
+            .collect::<Vec<_>>()
+            .join("+");
+        format!(
+            "{:?}: in {}, out {} {:?}, {} worker(s), {:.0}%",
+            self.kind,
+            input,
+            self.output,
+            recipe.output.0,
+            self.workers.len(),
+            self.progress * 100.
+        )
+    }
+}
+
+pub struct WorkshopTimer(Timer);
+
+pub fn spawn_workshop(
+    commands: &mut Commands,
+    materials: &Materials,
+    config: &GameConfig,
+    kind: WorkshopType,
+    island: Entity,
+    position: Vec2,
+) -> Entity {
+    let building = BuildingType::Workshop(kind);
+    commands
+        .spawn(SpriteBundle {
+            material: materials.workshop_material.clone(),
+            sprite: Sprite::new(building.size(config)),
+            transform: Transform::from_translation(vec3(position.x, position.y, 0.)),
+            ..Default::default()
+        })
+        .with(Workshop {
+            kind,
+            island,
+            workers: Vec::new(),
+            input: HashMap::default(),
+            output: 0,
+            progress: 0.,
+        })
+        .with(Building(building))
+        .current_entity()
+        .unwrap()
+}
+
+fn take_stock(ware: Ware, want: u32, stock: &mut IslandNR, goods: &mut IslandGoods) -> u32 {
+    let held = match ware {
+        Ware::Raw(nr_type) => stock.0.entry(nr_type).or_insert(0),
+        Ware::Good(good) => goods.0.entry(good).or_insert(0),
+    };
+    let taken = want.min(*held);
+    *held -= taken;
+    taken
+}
+
+pub fn give_stock(ware: Ware, amount: u32, stock: &mut IslandNR, goods: &mut IslandGoods) {
+    match ware {
+        Ware::Raw(nr_type) => *stock.0.entry(nr_type).or_insert(0) += amount,
+        Ware::Good(good) => *goods.0.entry(good).or_insert(0) += amount,
+    }
+}
+
+pub fn held(ware: Ware, stock: &IslandNR, goods: &IslandGoods) -> u32 {
+    match ware {
+        Ware::Raw(nr_type) => stock.0.get(&nr_type).copied().unwrap_or(0),
+        Ware::Good(good) => goods.0.get(&good).copied().unwrap_or(0),
+    }
+}
+
+pub fn can_afford_wares(stock: &IslandNR, goods: &IslandGoods, cost: &[(Ware, u32)]) -> bool {
+    cost.iter()
+        .all(|(ware, amount)| held(*ware, stock, goods) >= *amount)
+}
+
+pub fn pay_wares(stock: &mut IslandNR, goods: &mut IslandGoods, cost: &[(Ware, u32)]) {
+    for (ware, amount) in cost {
+        take_stock(*ware, *amount, stock, goods);
+    }
+}
+
+/// Staffs workshops with idle people from their island.
+fn assign_workers(
+    timer: Res<WorkshopTimer>,
+    clock: Res<WorldClock>,
+    houses_query: Query<&House>,
+    mut workshops_query: Query<(Entity, &GlobalTransform, &mut Workshop)>,
+    mut people_query: Query<(Entity, &mut Person, &mut TargetPosition, &OrderQueue)>,
+) {
+    if !timer.0.just_finished() {
+        return;
+    }
+    let hiring = clock.phase() == DayPhase::Day;
+    for (workshop_entity, transform, mut workshop) in workshops_query.iter_mut() {
+        // people who were ordered elsewhere or are gone stop working here
+        workshop.workers.retain(|worker| {
+            people_query
+                .get_component::<Person>(*worker)
+                .map_or(false, |person| {
+                    person.task == PersonTask::Working(workshop_entity)
+                })
+        });
+        // new hands only start during the day
+        if !hiring {
+            continue;
+        }
+        for (worker, mut person, mut target, orders) in people_query.iter_mut() {
+            if workshop.workers.len() >= MAX_WORKERS {
+                break;
+            }
+            let lives_here = person
+                .house
+                .and_then(|house| houses_query.get(house).ok())
+                .map_or(false, |house| house.island == workshop.island);
+            if !lives_here
+                || !orders.0.is_empty()
+                || !matches!(person.task, PersonTask::Idle | PersonTask::Wandering)
+            {
+                continue;
+            }
+            println!("{} starts working at a {:?}", person.name, workshop.kind);
+            person.task = PersonTask::Working(workshop_entity);
+            target.0 = Some(transform.translation.truncate());
+            workshop.workers.push(worker);
+        }
+    }
+}
+
+/// Every workshop with workers carries out its finished goods, restocks its inputs from the
+/// island and works on its current batch.
+fn run_workshops(
+    time: Res<GameTime>,
+    mut timer: ResMut<WorkshopTimer>,
+    mut workshops_query: Query<&mut Workshop>,
+    mut islands_query: Query<(&mut IslandNR, &mut IslandGoods)>,
+) {
+    if !timer.0.tick(time.delta_seconds()).just_finished() {
+        return;
+    }
+    for mut workshop in workshops_query.iter_mut() {
+        if workshop.workers.is_empty() {
+            continue;
+        }
+        let (mut stock, mut goods) = match islands_query.get_mut(workshop.island) {
+            Ok(found) => found,
+            Err(_) => continue,
+        };
+        let recipe = workshop.kind.recipe();
+        let (good, batch) = recipe.output;
+
+        if workshop.output > 0 {
+            *goods.0.entry(good).or_insert(0) += workshop.output;
+            workshop.output = 0;
+        }
+        for (ware, amount) in recipe.inputs.iter() {
+            let buffered = workshop.input.get(ware).copied().unwrap_or(0);
+            let want = (amount * INPUT_BATCHES).saturating_sub(buffered);
+            let taken = take_stock(*ware, want, &mut stock, &mut goods);
+            *workshop.input.entry(*ware).or_insert(0) += taken;
+        }
+
+        let has_inputs = recipe
+            .inputs
+            .iter()
+            .all(|(ware, amount)| workshop.input.get(ware).copied().unwrap_or(0) >= *amount);
+        if !has_inputs || workshop.output + batch > OUTPUT_CAPACITY {
+            continue;
+        }
+        workshop.progress +=
+            WORKSHOP_UPDATE_INTERVAL * workshop.workers.len() as f32 / recipe.duration;
+        if workshop.progress >= 1. {
+            workshop.progress = 0.;
+            for (ware, amount) in recipe.inputs.iter() {
+                if let Some(buffered) = workshop.input.get_mut(ware) {
+                    *buffered -= amount;
+                }
+            }
+            workshop.output += batch;
+        }
+    }
+}
+
+pub struct WorkshopPlugin;
+impl Plugin for WorkshopPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(WorkshopTimer(Timer::from_seconds(
+            WORKSHOP_UPDATE_INTERVAL,
+            true,
+        )))
+        .add_game_system(run_workshops.system())
+        .add_game_system(assign_workers.system());
+    }
+}